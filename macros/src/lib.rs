@@ -0,0 +1,180 @@
+//! the `#[component]` attribute and `#[derive(Component)]`, reexported by act2 with the `macros`
+//! feature
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, FnArg, Ident, ItemFn, Pat,
+    ReturnType, Type,
+};
+
+/// turns a function taking a `&mut Hook` as its first argument into a component. Every call
+/// becomes a render pass: the hook gets reset before the body runs, and afterwards it panics if
+/// the body used a different number of hooks than on its first render.
+///
+/// ```ignore
+/// #[component]
+/// fn counter(hook: &mut Hook, step: i32) -> i32 {
+///     let (count, set) = hook.use_state(0);
+///     set(count + step);
+///     count
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn component(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+        return Error::new_spanned(attr, "#[component] doesn't take any arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let function = parse_macro_input!(item as ItemFn);
+    match expand(function) {
+        Ok(function) => quote!(#function).into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(mut function: ItemFn) -> Result<ItemFn, Error> {
+    if let Some(asyncness) = function.sig.asyncness {
+        return Err(Error::new_spanned(
+            asyncness,
+            "components can't be async, hooks have to be called while rendering",
+        ));
+    }
+
+    let hook = hook_argument(&function)?;
+    let body = &function.block;
+
+    // the body runs in a closure, so returning early doesn't skip the hook count check
+    let run = match &function.sig.output {
+        ReturnType::Type(_, ty) if !matches!(**ty, Type::ImplTrait(_)) => {
+            quote!(|| -> #ty #body)
+        }
+        _ => quote!(|| #body),
+    };
+
+    function.block = Box::new(parse_quote!({
+        #hook.begin_render();
+        let output = (#run)();
+        if let Err(e) = #hook.end_render() {
+            panic!("{}", e);
+        }
+        output
+    }));
+
+    Ok(function)
+}
+
+/// name of the first argument, which has to hold the Hook
+fn hook_argument(function: &ItemFn) -> Result<Ident, Error> {
+    let message = "components need to take a `&mut Hook` as their first argument";
+
+    match function.sig.inputs.first() {
+        Some(FnArg::Typed(arg)) => match &*arg.pat {
+            Pat::Ident(pat) => Ok(pat.ident.clone()),
+            pat => Err(Error::new_spanned(pat, message)),
+        },
+        Some(receiver) => Err(Error::new_spanned(receiver, message)),
+        None => Err(Error::new_spanned(&function.sig, message)),
+    }
+}
+
+/// declares the state of a component as a struct, with one use_state hook per field. Generates
+/// `HOOK_COUNT`, `use_fields`, which calls the hooks in the order the fields are declared in, and
+/// `assert_hook_count`. The setters get returned in a struct named after the component, e.g.
+/// `FormSetters` for `Form`.
+///
+/// ```ignore
+/// #[derive(Component)]
+/// struct Form {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// let (form, setters) = Form::use_fields(hook, Form { name: String::new(), age: 0 });
+/// (setters.age)(form.age + 1);
+/// ```
+#[proc_macro_derive(Component)]
+pub fn derive_component(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    match derive(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn derive(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "#[derive(Component)] doesn't support generics",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            fields => {
+                return Err(Error::new_spanned(
+                    fields,
+                    "#[derive(Component)] needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "#[derive(Component)] needs a struct with named fields",
+            ))
+        }
+    };
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let setters = format_ident!("{}Setters", name);
+    let names: Vec<_> = fields.iter().map(|field| &field.ident).collect();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let count = fields.len();
+    let setters_doc = format!("setters for the fields of {name}, see {name}::use_fields");
+
+    Ok(quote! {
+        #[doc = #setters_doc]
+        #[allow(dead_code)]
+        #vis struct #setters {
+            #( #vis #names: ::act2::Setter<#types>, )*
+        }
+
+        #[allow(dead_code)]
+        impl #name {
+            /// number of hooks used by use_fields
+            #vis const HOOK_COUNT: usize = #count;
+
+            /// calls use_state for every field, in the order they are declared in, with the
+            /// fields of `init` as the initial values
+            #vis fn use_fields(hook: &mut ::act2::Hook, init: Self) -> (Self, #setters) {
+                let #name { #( #names ),* } = init;
+                #( let #names = hook.use_state(#names); )*
+
+                (
+                    #name { #( #names: #names.0 ),* },
+                    #setters { #( #names: #names.1 ),* },
+                )
+            }
+
+            /// panics unless `hook` used exactly HOOK_COUNT hooks since its render began
+            #vis fn assert_hook_count(hook: &::act2::Hook) {
+                let used = hook.hooks_used();
+                assert_eq!(
+                    used,
+                    Self::HOOK_COUNT,
+                    "{} declares {} hooks, but {} got used",
+                    stringify!(#name),
+                    Self::HOOK_COUNT,
+                    used
+                );
+            }
+        }
+    })
+}
@@ -0,0 +1,441 @@
+//! where time-based hooks like use_debounce get the current time from. Tests can swap in a
+//! TestClock, so they don't have to sleep.
+
+use crate::sync::{Lock, Shared};
+use crate::{MaybeSend, MaybeSync};
+#[cfg(feature = "thread-local")]
+use std::cell::RefCell;
+#[cfg(not(feature = "thread-local"))]
+use std::cmp::{Ordering, Reverse};
+#[cfg(not(feature = "thread-local"))]
+use std::collections::BinaryHeap;
+#[cfg(not(feature = "thread-local"))]
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, TestClock};
+    use crate::Runtime;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn advance() {
+        let clock = TestClock::new();
+        let start = clock.now();
+
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        for ms in [30, 10, 20] {
+            let fired = fired.clone();
+            clock.schedule(
+                Duration::from_millis(ms),
+                Box::new(move || fired.lock().unwrap().push(ms)),
+            );
+        }
+
+        clock.advance(Duration::from_millis(25));
+        assert_eq!(*fired.lock().unwrap(), [10, 20]);
+        assert_eq!(clock.now() - start, Duration::from_millis(25));
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(*fired.lock().unwrap(), [10, 20, 30]);
+    }
+
+    #[test]
+    fn advance_past_rescheduled() {
+        let clock = TestClock::new();
+        let start = clock.now();
+
+        // every wake schedules the next one, seeing the time it was due at
+        fn repeat(clock: TestClock, start: Instant, fired: Arc<Mutex<Vec<Duration>>>) {
+            let next = clock.clone();
+            clock.schedule(
+                Duration::from_millis(10),
+                Box::new(move || {
+                    fired.lock().unwrap().push(next.now() - start);
+                    repeat(next, start, fired);
+                }),
+            );
+        }
+
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        repeat(clock.clone(), start, fired.clone());
+        clock.advance(Duration::from_millis(35));
+
+        let ms = Duration::from_millis;
+        assert_eq!(*fired.lock().unwrap(), [ms(10), ms(20), ms(30)]);
+        assert_eq!(clock.now() - start, ms(35));
+    }
+
+    #[cfg(not(feature = "thread-local"))]
+    #[test]
+    fn system_clock_single_thread() {
+        use super::SystemClock;
+        use std::sync::mpsc;
+
+        let (sender, receiver) = mpsc::channel();
+        for ms in [30, 10, 20, 10] {
+            let sender = sender.clone();
+            SystemClock.schedule(
+                Duration::from_millis(ms),
+                Box::new(move || sender.send((ms, std::thread::current().id())).unwrap()),
+            );
+        }
+
+        let fired: Vec<_> = receiver.iter().take(4).collect();
+        let order: Vec<_> = fired.iter().map(|(ms, _)| *ms).collect();
+        assert_eq!(order, [10, 10, 20, 30]);
+        // every wake got called by the same timer thread
+        assert!(fired.iter().all(|(_, thread)| *thread == fired[0].1));
+        assert_ne!(fired[0].1, std::thread::current().id());
+    }
+
+    #[cfg(feature = "thread-local")]
+    #[test]
+    fn run_due() {
+        use super::SystemClock;
+        use std::rc::Rc;
+
+        let fired = Rc::new(std::cell::Cell::new(0));
+        for delay in [0, 10_000] {
+            let fired = fired.clone();
+            SystemClock.schedule(
+                Duration::from_millis(delay),
+                Box::new(move || fired.set(fired.get() + 1)),
+            );
+        }
+
+        // nothing gets called until the thread asks for it
+        assert_eq!(fired.get(), 0);
+        let next = SystemClock::run_due().unwrap();
+        assert_eq!(fired.get(), 1);
+        assert!(next > SystemClock.now());
+    }
+
+    #[test]
+    fn debounce_window() {
+        let clock = TestClock::new();
+        let rt = Runtime::with_clock(clock.clone());
+        let delay = Duration::from_secs(60);
+
+        assert_eq!(rt.hook().use_debounce(1, delay), 1);
+        assert_eq!(rt.hook().use_debounce(2, delay), 1);
+
+        // an hour would be a long test with a real clock
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(rt.hook().use_debounce(2, delay), 2);
+    }
+
+    #[test]
+    fn throttle() {
+        let clock = TestClock::new();
+        let rt = Runtime::with_clock(clock.clone());
+        let interval = Duration::from_secs(1);
+        let throttle = |value| rt.hook().use_throttle(value, interval);
+        let advance = |millis| clock.advance(Duration::from_millis(millis));
+
+        let mut emitted = vec![throttle(1)];
+        emitted.push(throttle(2));
+        advance(500);
+        emitted.push(throttle(3));
+        rt.take_dirty();
+
+        // the trailing 3 shows up once the window opens
+        advance(500);
+        assert!(rt.take_dirty());
+        emitted.push(throttle(3));
+        advance(200);
+        emitted.push(throttle(4));
+        advance(800);
+        emitted.push(throttle(4));
+        advance(5000);
+        emitted.push(throttle(5));
+
+        assert_eq!(emitted, [1, 1, 1, 3, 3, 4, 5]);
+    }
+
+    #[test]
+    fn deferred_value() {
+        let clock = TestClock::new();
+        let rt = Runtime::with_clock(clock.clone());
+
+        assert_eq!(rt.hook().use_deferred_value(0), 0);
+        for typed in 1..10 {
+            assert_eq!(rt.hook().use_deferred_value(typed), 0);
+        }
+
+        clock.advance(Duration::ZERO);
+        assert!(rt.take_dirty());
+        assert_eq!(rt.hook().use_deferred_value(10), 10);
+        assert_eq!(rt.hook().use_deferred_value(10), 10);
+    }
+
+    #[cfg(feature = "thread-local")]
+    #[test]
+    fn deferred_value_run_due() {
+        use super::SystemClock;
+
+        let rt = Runtime::new();
+        assert_eq!(rt.hook().use_deferred_value(0), 0);
+        assert_eq!(rt.hook().use_deferred_value(1), 0);
+
+        // the update waits for the thread to run it
+        assert_eq!(rt.hook().use_deferred_value(2), 0);
+        SystemClock::run_due();
+        assert!(rt.take_dirty());
+        assert_eq!(rt.hook().use_deferred_value(2), 2);
+    }
+}
+
+/// gets called by a Clock once some time passed
+#[cfg(not(feature = "thread-local"))]
+pub type Wake = Box<dyn FnOnce() + Send>;
+
+#[cfg(feature = "thread-local")]
+pub type Wake = Box<dyn FnOnce()>;
+
+pub trait Clock: MaybeSend + MaybeSync {
+    fn now(&self) -> Instant;
+
+    /// calls `wake` once `delay` passed
+    fn schedule(&self, delay: Duration, wake: Wake);
+}
+
+/// the clock used unless Runtime::with_clock says otherwise. Wakes get called one after the other
+/// by a single timer thread, so they should return quickly.
+///
+/// **With the `thread-local` feature, wakes only get called by SystemClock::run_due.** They can't
+/// be sent to a thread sleeping until they are due, so they get queued on the thread scheduling
+/// them instead. Unless that thread calls run_due, e.g. from its event loop, hooks relying on the
+/// clock like use_debounce, use_throttle, use_interval and use_deferred_value never catch up.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "thread-local")]
+thread_local! {
+    /// wakes scheduled by SystemClock on this thread, along with when they are due
+    static PENDING: RefCell<Vec<(Instant, Wake)>> = RefCell::default();
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// queues `wake` for the timer thread, which gets spawned on the first call. With the
+    /// `thread-local` feature `wake` gets queued for SystemClock::run_due instead.
+    fn schedule(&self, delay: Duration, wake: Wake) {
+        #[cfg(not(feature = "thread-local"))]
+        TIMER.schedule(Instant::now() + delay, wake);
+
+        #[cfg(feature = "thread-local")]
+        PENDING.with(|pending| pending.borrow_mut().push((Instant::now() + delay, wake)));
+    }
+}
+
+#[cfg(feature = "thread-local")]
+impl SystemClock {
+    /// calls every wake this thread scheduled that is due by now, in the order they are due.
+    /// Wakes scheduled by those wait for the next call. Returns when the next wake is due, so an
+    /// event loop knows how long it may sleep.
+    pub fn run_due() -> Option<Instant> {
+        let now = Instant::now();
+        let mut due = PENDING.with(|pending| {
+            let mut pending = pending.borrow_mut();
+            let (due, later) = std::mem::take(&mut *pending)
+                .into_iter()
+                .partition::<Vec<_>, _>(|(at, _)| *at <= now);
+
+            *pending = later;
+            due
+        });
+
+        // wakes may schedule new ones, so the queue must not be borrowed by now
+        due.sort_by_key(|(at, _)| *at);
+        for (_, wake) in due {
+            wake();
+        }
+
+        PENDING.with(|pending| pending.borrow().iter().map(|(at, _)| *at).min())
+    }
+}
+
+#[cfg(not(feature = "thread-local"))]
+lazy_static::lazy_static! {
+    static ref TIMER: Timer = Timer::spawn();
+}
+
+/// the wakes scheduled by SystemClock, for the thread calling them once they are due
+#[cfg(not(feature = "thread-local"))]
+struct Timer {
+    queue: Mutex<TimerQueue>,
+    /// notified whenever a wake got queued, which might be due before the one waited for
+    changed: Condvar,
+}
+
+#[cfg(not(feature = "thread-local"))]
+#[derive(Default)]
+struct TimerQueue {
+    timers: BinaryHeap<Reverse<Timed>>,
+    /// number of wakes queued so far, which keeps wakes due at the same time in order
+    scheduled: u64,
+}
+
+/// a wake along with when it is due, ordered by that
+#[cfg(not(feature = "thread-local"))]
+struct Timed {
+    at: Instant,
+    seq: u64,
+    wake: Wake,
+}
+
+#[cfg(not(feature = "thread-local"))]
+impl PartialEq for Timed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+#[cfg(not(feature = "thread-local"))]
+impl Eq for Timed {}
+
+#[cfg(not(feature = "thread-local"))]
+impl PartialOrd for Timed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(not(feature = "thread-local"))]
+impl Ord for Timed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+#[cfg(not(feature = "thread-local"))]
+impl Timer {
+    /// the timer along with its thread, which lives as long as the process
+    fn spawn() -> Self {
+        std::thread::Builder::new()
+            .name("act2-timer".into())
+            .spawn(|| TIMER.run())
+            .expect("failed to spawn the timer thread");
+
+        Timer {
+            queue: Mutex::default(),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn schedule(&self, at: Instant, wake: Wake) {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        let seq = queue.scheduled;
+        queue.scheduled += 1;
+        queue.timers.push(Reverse(Timed { at, seq, wake }));
+        drop(queue);
+
+        self.changed.notify_one();
+    }
+
+    /// calls every wake once it is due, sleeping in between
+    fn run(&self) {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+
+        loop {
+            let now = Instant::now();
+            let next = queue.timers.peek().map(|Reverse(timed)| timed.at);
+
+            queue = match next {
+                Some(at) if at <= now => {
+                    let Reverse(timed) = queue.timers.pop().expect("peeked just now");
+                    // wakes may schedule new ones, so the queue has to be unlocked by now. One
+                    // that panics must not stop the others.
+                    drop(queue);
+                    let wake = std::panic::AssertUnwindSafe(timed.wake);
+                    let _ = std::panic::catch_unwind(wake);
+                    self.queue.lock().unwrap_or_else(|e| e.into_inner())
+                }
+                Some(at) => match self.changed.wait_timeout(queue, at - now) {
+                    Ok((queue, _)) => queue,
+                    Err(e) => e.into_inner().0,
+                },
+                None => self.changed.wait(queue).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
+
+/// a clock that only moves when told to. Clones share the same time.
+#[derive(Clone)]
+pub struct TestClock {
+    start: Instant,
+    state: Shared<Lock<TestState>>,
+}
+
+#[derive(Default)]
+struct TestState {
+    elapsed: Duration,
+    /// scheduled wakes, along with when they are due
+    timers: Vec<(Duration, Wake)>,
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        TestClock {
+            start: Instant::now(),
+            state: Shared::default(),
+        }
+    }
+}
+
+impl TestClock {
+    pub fn new() -> Self {
+        TestClock::default()
+    }
+
+    /// moves the time forward by `by`, calling every wake that's due by then. The time stops at
+    /// each wake when calling it, so wakes scheduled by wakes get called as well if they are due
+    /// by then, e.g. every tick of use_interval. A wake that keeps scheduling itself without a
+    /// delay keeps this from returning.
+    pub fn advance(&self, by: Duration) {
+        let target = self.state.lock().elapsed + by;
+
+        loop {
+            let wake = {
+                let mut state = self.state.lock();
+                // the first one scheduled, out of those due first
+                let next = state
+                    .timers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (at, _))| *at <= target)
+                    .min_by_key(|(_, (at, _))| *at);
+
+                let Some((next, _)) = next else {
+                    state.elapsed = target;
+                    return;
+                };
+
+                let (at, wake) = state.timers.remove(next);
+                state.elapsed = state.elapsed.max(at);
+                wake
+            };
+
+            // wakes may schedule new ones, so the state has to be unlocked by now
+            wake();
+        }
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        self.start + self.state.lock().elapsed
+    }
+
+    fn schedule(&self, delay: Duration, wake: Wake) {
+        let mut state = self.state.lock();
+        let at = state.elapsed + delay;
+        state.timers.push((at, wake));
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+
+/// everything that can go wrong when using state. Most functions panic with one of these, their
+/// `try_` counterparts return it instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookError {
+    /// the register at `index` holds a different type than the hook asked for. This usually means
+    /// hooks got called in a different order than on the previous render.
+    TypeMismatch {
+        index: usize,
+        expected: &'static str,
+        /// name of the stored type, if known
+        found: Option<&'static str>,
+    },
+    /// some thread panicked while holding a lock on the state
+    PoisonedLock,
+    /// the state is locked by this very thread already, which only gets detected with the
+    /// `thread-local` feature
+    Locked,
+    /// a component used a different number of hooks than on its first render
+    HookOrder(HookOrderError),
+    /// the component at `node` used more hooks than Runtime::max_hooks_per_node allows, which
+    /// usually means it calls them in an unbounded loop
+    TooManyHooks { node: Vec<usize>, limit: usize },
+    /// a Setter got called after the state it belongs to got thrown away, e.g. by Runtime::reset,
+    /// so its register at `node` may belong to some other hook by now
+    StaleSetter { node: Vec<usize> },
+    /// there is no register at `index`, as the State only holds `len` of them
+    OutOfBounds { index: usize, len: usize },
+    /// there is no State at the cursor of the hook
+    MissingNode(TreeError),
+    /// a hook of Hook::use_state_keyed asked for a `expected`, but a hook with an equal key
+    /// stored a `found` before
+    KeyMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookError::TypeMismatch {
+                index,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "state hook #{index} expected {expected} but holds {found}"
+            ),
+            HookError::TypeMismatch {
+                index, expected, ..
+            } => write!(f, "state hook #{index} to be of type {expected}"),
+            HookError::PoisonedLock => write!(f, "state got poisoned by a panicking thread"),
+            HookError::Locked => write!(f, "state is locked by this thread already"),
+            HookError::HookOrder(e) => write!(f, "{e}"),
+            HookError::TooManyHooks { node, limit } => {
+                write!(f, "component at {node:?} used more than {limit} hooks")
+            }
+            HookError::StaleSetter { node } => {
+                write!(
+                    f,
+                    "setter outlived the state at {node:?}, which got reset or replaced"
+                )
+            }
+            HookError::OutOfBounds { index, len } => {
+                write!(f, "no state hook #{index}, there are only {len}")
+            }
+            HookError::MissingNode(e) => write!(f, "{e}"),
+            HookError::KeyMismatch { expected, found } => {
+                write!(f, "keyed state hook expected {expected} but holds {found}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HookError {}
+
+impl From<TreeError> for HookError {
+    fn from(e: TreeError) -> Self {
+        HookError::MissingNode(e)
+    }
+}
+
+/// some component broke the rules of hooks by calling a different number of them than on its
+/// first render, so hooks can't be matched to their state anymore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookOrderError {
+    /// cursor of the component in the StateTree
+    pub path: Vec<usize>,
+    /// number of hooks used on the first render
+    pub expected: usize,
+    /// number of hooks used on this render
+    pub actual: usize,
+}
+
+impl fmt::Display for HookOrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "component at {:?} used {} hooks, but {} on its first render",
+            self.path, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for HookOrderError {}
+
+/// a cursor pointing to a child that doesn't exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeError {
+    /// position in the cursor of the offending slot
+    pub depth: usize,
+    /// the slot that got asked for
+    pub index: usize,
+    /// number of children at that depth
+    pub len: usize,
+}
+
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "no child #{} at depth {}, there are only {}",
+            self.index, self.depth, self.len
+        )
+    }
+}
+
+impl std::error::Error for TreeError {}
+
+/// a component rendered with Hook::use_error_boundary panicked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundaryError {
+    /// cursor of the component that panicked
+    pub path: Vec<usize>,
+    /// the message it panicked with
+    pub message: String,
+}
+
+impl fmt::Display for BoundaryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "component at {:?} panicked: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for BoundaryError {}
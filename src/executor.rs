@@ -0,0 +1,181 @@
+//! where the futures of use_async get run. Any async runtime can be plugged in by implementing
+//! Executor, tokio comes with the `tokio` feature.
+
+use crate::sync::Lock;
+use crate::{MaybeSend, MaybeSync};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+/// a future spawned by use_async, which writes its result to the state once it completes
+#[cfg(not(feature = "thread-local"))]
+pub type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+#[cfg(feature = "thread-local")]
+pub type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+pub trait Executor: MaybeSend + MaybeSync {
+    /// runs `task` to completion
+    fn spawn(&self, task: Task);
+}
+
+/// runs every task to completion right away, blocking the render that spawned it. That's what
+/// a runtime with the `thread-local` feature uses unless told otherwise, as tasks can't leave
+/// their thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlockingExecutor;
+
+impl Executor for BlockingExecutor {
+    fn spawn(&self, task: Task) {
+        block_on(task);
+    }
+}
+
+/// runs every task on a thread of its own
+#[cfg(not(feature = "thread-local"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadExecutor;
+
+#[cfg(not(feature = "thread-local"))]
+impl Executor for ThreadExecutor {
+    fn spawn(&self, task: Task) {
+        std::thread::spawn(move || block_on(task));
+    }
+}
+
+/// hands every task to a tokio runtime
+#[cfg(all(feature = "tokio", not(feature = "thread-local")))]
+#[derive(Clone, Debug)]
+pub struct TokioExecutor {
+    handle: tokio::runtime::Handle,
+}
+
+#[cfg(all(feature = "tokio", not(feature = "thread-local")))]
+impl TokioExecutor {
+    /// spawns on the tokio runtime this gets called from. Panics outside of a tokio runtime,
+    /// see tokio::runtime::Handle::current.
+    pub fn current() -> Self {
+        TokioExecutor {
+            handle: tokio::runtime::Handle::current(),
+        }
+    }
+}
+
+#[cfg(all(feature = "tokio", not(feature = "thread-local")))]
+impl From<tokio::runtime::Handle> for TokioExecutor {
+    fn from(handle: tokio::runtime::Handle) -> Self {
+        TokioExecutor { handle }
+    }
+}
+
+#[cfg(all(feature = "tokio", not(feature = "thread-local")))]
+impl Executor for TokioExecutor {
+    fn spawn(&self, task: Task) {
+        self.handle.spawn(task);
+    }
+}
+
+/// what a Runtime spawns tasks on unless Runtime::with_executor says otherwise
+#[cfg(not(feature = "thread-local"))]
+pub(crate) type DefaultExecutor = ThreadExecutor;
+
+#[cfg(feature = "thread-local")]
+pub(crate) type DefaultExecutor = BlockingExecutor;
+
+/// work spawned by a hook that keeps going after the render, see Runtime::pending_tasks
+pub(crate) trait Cancel: MaybeSend + MaybeSync {
+    /// stops the task, dropping whatever it holds on to
+    fn cancel(&self);
+
+    /// whether the task completed or got cancelled
+    fn finished(&self) -> bool;
+}
+
+/// the flag of use_interval, which stops the interval once set
+impl Cancel for AtomicBool {
+    fn cancel(&self) {
+        self.store(true, Ordering::SeqCst);
+    }
+
+    fn finished(&self) -> bool {
+        self.load(Ordering::SeqCst)
+    }
+}
+
+/// a task that drops its future once cancelled, even if the executor never polls it again
+pub(crate) struct Abortable {
+    future: Lock<Option<Task>>,
+    cancelled: AtomicBool,
+    done: AtomicBool,
+}
+
+impl Abortable {
+    pub(crate) fn new(task: Task) -> Self {
+        Abortable {
+            future: Lock::new(Some(task)),
+            cancelled: AtomicBool::new(false),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// polls the future unless the task got cancelled. The future gets taken out while polling
+    /// it, so it may cancel tasks itself.
+    pub(crate) fn poll(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(mut future) = self.future.lock().take() else {
+            return Poll::Ready(());
+        };
+
+        if future.as_mut().poll(cx).is_ready() {
+            self.done.store(true, Ordering::SeqCst);
+            return Poll::Ready(());
+        }
+
+        let mut slot = self.future.lock();
+        if self.cancelled.load(Ordering::SeqCst) {
+            drop(slot);
+            drop(future);
+            return Poll::Ready(());
+        }
+
+        *slot = Some(future);
+        Poll::Pending
+    }
+}
+
+impl Cancel for Abortable {
+    fn cancel(&self) {
+        let future = {
+            let mut slot = self.future.lock();
+            self.cancelled.store(true, Ordering::SeqCst);
+            slot.take()
+        };
+
+        // the future may use state once dropped, so the slot has to be unlocked by then
+        drop(future);
+    }
+
+    fn finished(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst) || self.done.load(Ordering::SeqCst)
+    }
+}
+
+/// unparks the thread polling a future
+struct Unpark(std::thread::Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// polls `task` on the current thread, sleeping whenever it's pending
+pub(crate) fn block_on(mut task: Task) {
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    while task.as_mut().poll(&mut cx).is_pending() {
+        std::thread::park();
+    }
+}
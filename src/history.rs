@@ -0,0 +1,195 @@
+//! time-travel debugging: every value passed to a use_state setter gets recorded, so the writes
+//! can be stepped through with Runtime::undo and Runtime::redo.
+
+use crate::state::{Register, StateTree};
+use std::collections::VecDeque;
+
+#[cfg(test)]
+mod tests {
+    use crate::Runtime;
+
+    #[test]
+    fn undo_redo() {
+        let rt = Runtime::new();
+        let read = || rt.hook().use_state(0).0;
+
+        let (_, set) = rt.hook().use_state(0);
+        set(1);
+        set(2);
+        set(3);
+
+        assert!(rt.undo());
+        assert!(rt.undo());
+        assert_eq!(read(), 1);
+        assert!(rt.redo());
+        assert_eq!(read(), 2);
+
+        let history = rt.history();
+        assert_eq!(history.len(), 3);
+        assert!(!history[1].undone);
+        assert!(history[2].undone);
+        assert_eq!(history[0].type_name, "i32");
+
+        // a new write drops everything that got undone
+        set(4);
+        assert!(!rt.redo());
+        assert_eq!(rt.history().len(), 3);
+        assert!(rt.undo());
+        assert_eq!(read(), 2);
+    }
+
+    #[test]
+    fn undo_counts_as_write() {
+        let rt = Runtime::new();
+        let (_, set) = rt.hook().use_state(0);
+        set(1);
+
+        let written = set.last_seq();
+        assert!(rt.undo());
+        assert!(set.last_seq() > written);
+
+        let undone = set.last_seq();
+        assert!(rt.redo());
+        assert!(set.last_seq() > undone);
+        assert_eq!(rt.hook().use_state(0).0, 1);
+    }
+
+    #[test]
+    fn bounded() {
+        let rt = Runtime::with_history_capacity(2);
+
+        let (_, set) = rt.hook().use_state(0);
+        for value in 1..=5 {
+            set(value);
+        }
+
+        assert_eq!(rt.history().len(), 2);
+        assert!(rt.undo());
+        assert!(rt.undo());
+        assert!(!rt.undo());
+        assert_eq!(rt.hook().use_state(0).0, 3);
+    }
+}
+
+/// number of writes a Runtime remembers unless told otherwise
+pub(crate) const DEFAULT_CAPACITY: usize = 100;
+
+/// a write recorded by the history of a Runtime
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// points to the State holding the register
+    pub cursor: Vec<usize>,
+    /// the register that got written to
+    pub index: usize,
+    /// type of the value that got written
+    pub type_name: &'static str,
+    /// whether the write got reverted by Runtime::undo
+    pub undone: bool,
+}
+
+struct Record {
+    cursor: Vec<usize>,
+    index: usize,
+    type_name: &'static str,
+    /// the register before the write, or after it once the write got undone. Undoing and
+    /// redoing just swaps its value with the one in the StateTree.
+    register: Register,
+}
+
+/// ring buffer of the most recent writes
+pub(crate) struct History {
+    records: VecDeque<Record>,
+    /// number of records that are applied, everything after got undone
+    position: usize,
+    capacity: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl History {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        History {
+            records: VecDeque::with_capacity(capacity),
+            position: 0,
+            capacity,
+        }
+    }
+
+    /// remembers that register `index` of the State at `cursor` held `previous` before a write
+    pub(crate) fn record(
+        &mut self,
+        cursor: Vec<usize>,
+        index: usize,
+        type_name: &'static str,
+        previous: Register,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        // writes after an undo start a new timeline
+        self.records.truncate(self.position);
+
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+
+        self.records.push_back(Record {
+            cursor,
+            index,
+            type_name,
+            register: previous,
+        });
+        self.position = self.records.len();
+    }
+
+    pub(crate) fn changes(&self) -> Vec<Change> {
+        self.records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| Change {
+                cursor: record.cursor.clone(),
+                index: record.index,
+                type_name: record.type_name,
+                undone: i >= self.position,
+            })
+            .collect()
+    }
+
+    pub(crate) fn undo(&mut self, tree: &StateTree) -> bool {
+        if self.position == 0 {
+            return false;
+        }
+
+        self.position -= 1;
+        self.swap(self.position, tree);
+        true
+    }
+
+    pub(crate) fn redo(&mut self, tree: &StateTree) -> bool {
+        if self.position == self.records.len() {
+            return false;
+        }
+
+        self.swap(self.position, tree);
+        self.position += 1;
+        true
+    }
+
+    /// exchanges the value of record `i` with the register it belongs to. Registers that got
+    /// unmounted in the meantime are left alone.
+    fn swap(&mut self, i: usize, tree: &StateTree) {
+        let record = &mut self.records[i];
+
+        if let Ok(state) = tree.try_get_state(&record.cursor) {
+            if let Some(mut register) = state.write().get_mut(record.index) {
+                register.swap_value(&mut record.register);
+                state.mark_dirty();
+            }
+        }
+    }
+}
@@ -1,164 +1,4132 @@
-use lazy_static::lazy_static;
-use std::any::Any;
-use std::sync::{Mutex, MutexGuard, RwLock};
+use crate::clock::Clock;
+use crate::pass::RenderPass;
+#[cfg(not(feature = "thread-local"))]
+use crate::runtime::WeakRuntime;
+#[cfg(not(feature = "thread-local"))]
+use crate::state::State;
+use crate::state::{AnyBox, Generation, HookId, Register, Registers, SharedRegister, StateTree};
+use crate::sync::{Lock, ReadGuard, Shared};
+use crate::{BoundaryError, HookError, MaybeSend, MaybeSync, Runtime, Transition};
+use smallvec::SmallVec;
+use std::any::TypeId;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::panic::{AssertUnwindSafe, Location};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(feature = "thread-local"))]
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex, MutexGuard};
+    use std::time::Duration;
+
+    /// all tests share the global StateTree, so they have to take turns and start from scratch
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    fn fresh() -> MutexGuard<'static, ()> {
+        let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        crate::reset_global();
+        guard
+    }
+
     #[test]
     fn recover_state() {
+        crate::with_isolated_state(|| {
+            let mut hook = super::Hook::default();
+            let (_, _) = hook.use_state("what");
+            let (_, _) = hook.use_state(123);
+            let (_, _) = hook.use_state(3.145);
+            let (_, _) = hook.use_state(true);
+
+            let mut hook = super::Hook::default();
+
+            let (a, _) = hook.use_state("no");
+            let (b, _) = hook.use_state(1231);
+            let (c, _) = hook.use_state(3.14325);
+            let (d, _) = hook.use_state(false);
+
+            assert_eq!(a, "what");
+            assert_eq!(b, 123);
+            assert_eq!(c, 3.145);
+            assert!(d);
+        });
+    }
+
+    #[test]
+    fn set_state() {
+        crate::with_isolated_state(|| {
+            let mut hook = super::Hook::default();
+            let (_, set_a) = hook.use_state("what");
+            let (_, set_b) = hook.use_state(123);
+            let (_, set_c) = hook.use_state(3.145);
+            let (_, set_d) = hook.use_state(true);
+            set_a("möp");
+            set_b(314);
+            set_c(0.0);
+            set_d(false);
+
+            let mut hook = super::Hook::default();
+
+            let (a, _) = hook.use_state("what");
+            let (b, _) = hook.use_state(123);
+            let (c, _) = hook.use_state(3.145);
+            let (d, _) = hook.use_state(true);
+
+            assert_eq!(a, "möp");
+            assert_eq!(b, 314);
+            assert_eq!(c, 0.0);
+            assert!(!d);
+        });
+    }
+
+    #[test]
+    fn reducer() {
+        let _serial = fresh();
+
+        fn count(state: i32, action: i32) -> i32 {
+            state + action
+        }
+
+        let mut hook = super::Hook::default();
+        let (value, dispatch) = hook.use_reducer(0, count);
+        assert_eq!(value, 0);
+        dispatch(2);
+        dispatch(3);
+
+        let mut hook = super::Hook::default();
+        let (value, _) = hook.use_reducer(0, count);
+        assert_eq!(value, 5);
+    }
+
+    #[test]
+    fn memo() {
+        let _serial = fresh();
+
+        let mut hook = super::Hook::default();
+        let value = hook.use_memo((1, "a"), || 42);
+        assert_eq!(value, 42);
+
+        let mut hook = super::Hook::default();
+        let value = hook.use_memo((1, "a"), || -> i32 { panic!("deps did not change") });
+        assert_eq!(value, 42);
+
+        let mut hook = super::Hook::default();
+        let value = hook.use_memo((2, "a"), || 7);
+        assert_eq!(value, 7);
+    }
+
+    #[test]
+    fn memo_hashed() {
+        let rt = crate::Runtime::new();
+        let calls = Arc::new(Mutex::new(0));
+        let render = |deps: &Vec<Vec<u32>>| {
+            let calls = calls.clone();
+            rt.hook().use_memo_hashed(deps, move || {
+                *calls.lock().unwrap() += 1;
+                deps.iter().flatten().sum::<u32>()
+            })
+        };
+
+        let mut deps = vec![vec![1, 2], vec![3]];
+        assert_eq!(render(&deps), 6);
+        assert_eq!(render(&deps.clone()), 6);
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        deps[1][0] = 4;
+        assert_eq!(render(&deps), 7);
+        assert_eq!(*calls.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn effect() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let _serial = fresh();
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let cleanups = Arc::new(AtomicUsize::new(0));
+
+        let render = |deps: i32| {
+            let mut hook = super::Hook::default();
+            hook.use_effect(deps, || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                let cleanups = cleanups.clone();
+                Box::new(move || {
+                    cleanups.fetch_add(1, Ordering::SeqCst);
+                })
+            });
+        };
+
+        render(1);
+        render(1);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(cleanups.load(Ordering::SeqCst), 0);
+
+        render(2);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(cleanups.load(Ordering::SeqCst), 1);
+
+        // dropping the node the effect lives in cleans up as well
+        crate::reset_global();
+        assert_eq!(cleanups.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn reference() {
+        let _serial = fresh();
+
+        let mut hook = super::Hook::default();
+        let counter = hook.use_ref(0);
+        *counter.lock().unwrap() += 1;
+
+        let mut hook = super::Hook::default();
+        let same = hook.use_ref(0);
+        assert!(Arc::ptr_eq(&counter, &same));
+        assert_eq!(*same.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn latest_ref() {
+        let rt = crate::Runtime::new();
+        let latest = rt.hook().use_latest_ref(String::from("first"));
+        assert_eq!(*latest.read().unwrap(), "first");
+
+        let same = rt.hook().use_latest_ref(String::from("second"));
+        assert!(Arc::ptr_eq(&latest, &same));
+        rt.hook().use_latest_ref(String::from("third"));
+
+        // e.g. a future spawned on the first render
+        assert_eq!(*latest.read().unwrap(), "third");
+    }
+
+    #[test]
+    fn lazy_init() {
+        let _serial = fresh();
+
+        let mut calls = 0;
+        for _ in 0..3 {
+            let mut hook = super::Hook::default();
+            let (value, _) = hook.use_state_with(|| {
+                calls += 1;
+                "expensive".to_string()
+            });
+            assert_eq!(value, "expensive");
+        }
+
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn state_tracked() {
+        let rt = crate::Runtime::new();
+        let render = || {
+            let mut hook = rt.hook();
+            let (count, set, created) = hook.use_state_tracked(0);
+            set(count + 1);
+            let (_, _, child_created) = hook.with_child(|hook| hook.use_state_tracked("child"));
+            (count, created, child_created)
+        };
+
+        assert_eq!(render(), (0, true, true));
+        assert_eq!(render(), (1, false, false));
+        rt.hook().unmount_child(0);
+        assert_eq!(render(), (2, false, true));
+    }
+
+    #[test]
+    fn state_default() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DEFAULTS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone, Debug, PartialEq)]
+        struct Settings(u8);
+
+        impl Default for Settings {
+            fn default() -> Self {
+                DEFAULTS.fetch_add(1, Ordering::SeqCst);
+                Settings(1)
+            }
+        }
+
+        let rt = crate::Runtime::new();
+        let (settings, set) = rt.hook().use_state_default::<Settings>();
+        assert_eq!(settings, Settings(1));
+        set(Settings(2));
+
+        for _ in 0..3 {
+            assert_eq!(rt.hook().use_state_default::<Settings>().0, Settings(2));
+        }
+        assert_eq!(DEFAULTS.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(not(feature = "thread-local"))]
+    #[test]
+    fn functional_update() {
+        let _serial = fresh();
+
+        let mut hook = super::Hook::default();
+        let (value, update) = hook.use_state_fn(0);
+        assert_eq!(value, 0);
+
+        // both updates see the value written by the one before, even though `value` is stale
+        update.update(|v| v + 1);
+        update.update(|v| v + 1);
+
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        update.update(|v| v + 1);
+                    }
+                });
+            }
+        });
+
+        let mut hook = super::Hook::default();
+        let (value, _) = hook.use_state_fn(0);
+        assert_eq!(value, 202);
+    }
+
+    #[test]
+    fn skip_equal_writes() {
+        let _serial = fresh();
+
+        let register = || {
+            let runtime = super::Runtime::global();
+            let tree = runtime.lock();
+            let registers = tree.get_state(&[]).read();
+            let register = registers.get(0).unwrap();
+            register.as_any() as *const dyn std::any::Any as *const ()
+        };
+
+        let mut hook = super::Hook::default();
+        let (_, set) = hook.use_state_eq(true);
+        let before = register();
+
+        assert!(!set(true));
+        assert_eq!(register(), before);
+
+        assert!(set(false));
+        let mut hook = super::Hook::default();
+        let (value, _) = hook.use_state_eq(true);
+        assert!(!value);
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let _serial = fresh();
+
+        let mut hook = super::Hook::default();
+        let (_, _) = hook.use_state(1u8);
+
+        let mut hook = super::Hook::default();
+        let err = hook.try_use_state("not a number").err().unwrap();
+        assert_eq!(
+            err,
+            super::HookError::TypeMismatch {
+                index: 0,
+                expected: "&str",
+                found: Some("u8")
+            }
+        );
+        assert_eq!(err.to_string(), "state hook #0 expected &str but holds u8");
+
+        let mut hook = super::Hook::default();
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| hook.use_state(0i64)))
+            .err()
+            .unwrap();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert_eq!(message, "state hook #0 expected i64 but holds u8");
+    }
+
+    #[cfg(not(feature = "thread-local"))]
+    #[test]
+    fn try_errors() {
+        use super::HookError;
+        use crate::TreeError;
+
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(1u8);
+        let mut child = rt.hook_at(&[0]);
+
+        assert_eq!(rt.try_peek::<u8>(&[], 0), Ok(1));
+        assert_eq!(
+            rt.try_peek::<u8>(&[], 1),
+            Err(HookError::OutOfBounds { index: 1, len: 1 })
+        );
+        assert_eq!(
+            hook.try_read_state(0, |value: &i32| *value),
+            Err(HookError::TypeMismatch {
+                index: 0,
+                expected: "i32",
+                found: Some("u8")
+            })
+        );
+        assert_eq!(
+            rt.try_peek::<u8>(&[2], 0),
+            Err(HookError::MissingNode(TreeError {
+                depth: 0,
+                index: 2,
+                len: 1
+            }))
+        );
+
+        // the child is gone after a reset, along with the rest of the state
+        rt.reset();
+        assert_eq!(
+            child.try_use_state(0).err(),
+            Some(HookError::MissingNode(TreeError {
+                depth: 0,
+                index: 0,
+                len: 0
+            }))
+        );
+        assert_eq!(
+            child.try_use_state_keyed("key", 0).err(),
+            Some(HookError::MissingNode(TreeError {
+                depth: 0,
+                index: 0,
+                len: 0
+            }))
+        );
+
+        let poisoner = rt.clone();
+        let panicked = std::thread::spawn(move || {
+            let _tree = poisoner.try_lock().unwrap();
+            panic!("render went wrong");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert_eq!(
+            hook.try_read_state(0, |value: &u8| *value),
+            Err(HookError::PoisonedLock)
+        );
+        assert_eq!(rt.try_peek::<u8>(&[], 0), Err(HookError::PoisonedLock));
+    }
+
+    #[cfg(not(feature = "thread-local"))]
+    #[test]
+    fn recover_from_poison() {
+        let _serial = fresh();
+
+        let mut hook = super::Hook::default();
+        let (_, set) = hook.use_state(1);
+
+        let panicked = std::thread::spawn(|| {
+            let runtime = super::Runtime::global();
+            let _tree = runtime.try_lock().unwrap();
+            panic!("render went wrong");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(super::Runtime::global().try_lock().is_err());
+
+        set(2);
+        let mut hook = super::Hook::default();
+        let (value, _) = hook.use_state(1);
+        assert_eq!(value, 2);
+        assert!(hook.try_use_state(true).is_ok());
+    }
+
+    #[test]
+    fn unused_registers() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let mut render = |hooks: usize| {
+            hook.begin_render();
+            for _ in 0..hooks {
+                let (_, _) = hook.use_state(0);
+            }
+            let _ = hook.end_render();
+            rt.lock().get_state(&[]).read().len()
+        };
+
+        assert_eq!(render(4), 4);
+        assert_eq!(render(2), 2);
+        assert_eq!(render(3), 3);
+    }
+
+    #[test]
+    fn render_passes() {
+        let _serial = fresh();
+
+        let mut hook = super::Hook::default();
+        for expected in 0..3 {
+            hook.begin_render();
+            let (value, set) = hook.use_state(0);
+            let (_, _) = hook.use_state("second");
+            assert_eq!(value, expected);
+            set(value + 1);
+            assert_eq!(hook.end_render(), Ok(()));
+        }
+
+        hook.begin_render();
+        let (_, _) = hook.use_state(0);
+        assert_eq!(
+            hook.end_render(),
+            Err(super::HookError::HookOrder(crate::HookOrderError {
+                path: vec![],
+                expected: 2,
+                actual: 1
+            }))
+        );
+
+        // children get checked as well, and so do fresh hooks
+        let rt = crate::Runtime::new();
+        let render = |hooks: usize| {
+            let mut hook = rt.hook();
+            hook.with_child(|hook| hook.with_keyed_child("nested", |_| ()));
+            hook.with_child(|hook| {
+                hook.with_child(|hook| {
+                    for _ in 0..hooks {
+                        hook.use_state(0);
+                    }
+                })
+            });
+            hook.end_render()
+        };
+
+        assert_eq!(render(4), Ok(()));
+        assert_eq!(render(4), Ok(()));
+        let error = render(3).unwrap_err();
+        assert_eq!(
+            error,
+            super::HookError::HookOrder(crate::HookOrderError {
+                path: vec![1, 0],
+                expected: 4,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "component at [1, 0] used 3 hooks, but 4 on its first render"
+        );
+    }
+
+    #[test]
+    fn children() {
+        let _serial = fresh();
+
+        let render = |hook: &mut super::Hook| {
+            let (parent, _) = hook.use_state("parent");
+            let (a, set_a) = hook.with_child(|hook| hook.use_state(1));
+            let (b, set_b) = hook.with_child(|hook| hook.use_state(2));
+            set_a(a * 10);
+            set_b(b * 100);
+            (parent, a, b)
+        };
+
+        assert_eq!(render(&mut super::Hook::default()), ("parent", 1, 2));
+        assert_eq!(render(&mut super::Hook::default()), ("parent", 10, 200));
+    }
+
+    #[test]
+    fn keyed_children() {
+        let _serial = fresh();
+
+        let render = |keys: &[&str]| {
+            let mut hook = super::Hook::default();
+            keys.iter()
+                .map(|key| {
+                    hook.with_keyed_child(key, |hook| {
+                        let (count, set) = hook.use_state(0);
+                        set(count + 1);
+                        (key.to_string(), count)
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let owned = |items: &[(&str, i32)]| {
+            items
+                .iter()
+                .map(|(key, count)| (key.to_string(), *count))
+                .collect::<Vec<_>>()
+        };
+
+        render(&["a", "b", "c"]);
+        render(&["b"]);
+        assert_eq!(
+            render(&["c", "a", "b"]),
+            owned(&[("c", 1), ("a", 1), ("b", 2)])
+        );
+        assert_eq!(
+            render(&["d", "c", "a", "b"]),
+            owned(&[("d", 0), ("c", 2), ("a", 2), ("b", 3)])
+        );
+
+        // positional children don't get mixed up with keyed ones
+        let mut hook = super::Hook::default();
+        let (count, _) = hook.with_child(|hook| hook.use_state(0));
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn unmount() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let _serial = fresh();
+
+        let cleanups = Arc::new(AtomicUsize::new(0));
+        let render = |hook: &mut super::Hook| {
+            hook.with_child(|hook| {
+                hook.with_child(|hook| {
+                    let cleanups = cleanups.clone();
+                    hook.use_effect((), move || {
+                        Box::new(move || {
+                            cleanups.fetch_add(1, Ordering::SeqCst);
+                        })
+                    });
+                });
+
+                let (value, set) = hook.use_state(0);
+                set(value + 1);
+                value
+            })
+        };
+
+        let mut hook = super::Hook::default();
+        assert_eq!(render(&mut hook), 0);
+
+        hook.begin_render();
+        assert_eq!(render(&mut hook), 1);
+
+        hook.unmount_child(0);
+        assert_eq!(cleanups.load(Ordering::SeqCst), 1);
+
+        hook.begin_render();
+        assert_eq!(render(&mut hook), 0);
+    }
+
+    #[test]
+    fn reuse_slots() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let (_, set) = hook.with_child(|hook| hook.use_state(0));
+        set(1);
+        hook.with_child(|hook| hook.use_state(2));
+
+        hook.unmount_child(0);
+        let (value, _) = hook.with_keyed_child("new", |hook| hook.use_state(3));
+        assert_eq!(value, 3);
+        {
+            let tree = rt.lock();
+            assert_eq!(tree.keys["new"], 0);
+            assert_eq!(tree.children.len(), 2);
+            assert_eq!(tree.children[0].state.read().len(), 1);
+        }
+
+        // the unmounted child gets a slot of its own once it's rendered again
+        let mut hook = rt.hook();
+        assert_eq!(hook.with_child(|hook| hook.use_state(4)).0, 4);
+        assert_eq!(rt.lock().positional, [Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn prune() {
+        let _serial = fresh();
+
+        let render = |hook: &mut super::Hook, children: usize, keyed: &[&str]| {
+            hook.begin_render();
+            let mut values = Vec::new();
+
+            let mut count = |hook: &mut super::Hook| {
+                let (value, set) = hook.use_state(0);
+                set(value + 1);
+                values.push(value);
+            };
+            for _ in 0..children {
+                hook.with_child(&mut count);
+            }
+            for key in keyed {
+                hook.with_keyed_child(key, &mut count);
+            }
+
+            hook.prune_unvisited();
+            values
+        };
+
+        let mut hook = super::Hook::default();
+        assert_eq!(render(&mut hook, 2, &["a", "b"]), [0, 0, 0, 0]);
+        assert_eq!(render(&mut hook, 1, &["b"]), [1, 1]);
+        assert_eq!(render(&mut hook, 2, &["a", "b"]), [2, 0, 0, 2]);
+    }
+
+    #[cfg(feature = "thread-local")]
+    #[test]
+    fn not_send() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let _serial = fresh();
+
+        let mut hook = super::Hook::default();
+        let (handle, _) = hook.use_state(Rc::new(Cell::new(1)));
+        handle.set(2);
+
+        let mut hook = super::Hook::default();
+        let (handle, _) = hook.use_state(Rc::new(Cell::new(1)));
+        assert_eq!(handle.get(), 2);
+    }
+
+    #[test]
+    fn shared_state() {
+        let _serial = fresh();
+
+        // doesn't implement Clone, so reading it can't possibly copy it
+        struct Big(Vec<u64>);
+
+        let mut hook = super::Hook::default();
+        let (first, set) = hook.use_state_shared(Big(vec![7; 1 << 20]));
+
+        let mut hook = super::Hook::default();
+        let (second, _) = hook.use_state_shared(Big(Vec::new()));
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(second.0.len(), 1 << 20);
+
+        set(Big(vec![1]));
         let mut hook = super::Hook::default();
-        let (_, _) = hook.use_state("what");
-        let (_, _) = hook.use_state(123);
-        let (_, _) = hook.use_state(3.145);
-        let (_, _) = hook.use_state(true);
+        let (third, _) = hook.use_state_shared(Big(Vec::new()));
+        assert_eq!(third.0, [1]);
+    }
+
+    #[cfg(not(feature = "thread-local"))]
+    #[test]
+    fn set_during_render() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let (done, finished) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut hook = super::Runtime::new().hook();
+            let (value, set) = hook.use_state(1);
+            set(value + 1);
+            let (_, set) = hook.use_state_with(|| 1);
+            set(3);
+            let (_, set) = hook.try_use_state(1).unwrap();
+            set(4);
+            done.send(()).unwrap();
+        });
+
+        finished
+            .recv_timeout(Duration::from_secs(5))
+            .expect("setter called during render to not deadlock");
+    }
+
+    #[test]
+    fn named_setter() {
+        let rt = crate::Runtime::new();
+        let mut setters: Vec<super::Setter<i32>> = Vec::new();
+
+        let (_, set) = rt.hook().use_state(0);
+        setters.push(set.clone());
+        setters.push(set);
+
+        setters[0](1);
+        assert_eq!(rt.hook().use_state(0).0, 1);
+        setters[1].call(2);
+        assert_eq!(rt.hook().use_state(0).0, 2);
+    }
+
+    #[test]
+    fn replace() {
+        let rt = crate::Runtime::new();
+
+        let (_, set) = rt.hook().use_state(0);
+        set(5);
+        assert_eq!(set.replace(10), 5);
+        assert_eq!(rt.hook().use_state(0).0, 10);
+    }
+
+    #[test]
+    fn context() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Theme(&'static str);
+
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        hook.provide_context(Theme("dark"));
+
+        let nested = hook.with_child(|hook| hook.with_child(|hook| hook.use_context::<Theme>()));
+        assert_eq!(nested, Some(Theme("dark")));
+
+        let shadowed = hook.with_child(|hook| {
+            hook.provide_context(Theme("light"));
+            hook.with_child(|hook| hook.use_context::<Theme>())
+        });
+        assert_eq!(shadowed, Some(Theme("light")));
+        assert_eq!(hook.use_context::<Theme>(), Some(Theme("dark")));
+        assert_eq!(hook.use_context::<String>(), None);
+    }
+
+    #[test]
+    fn context_keyed() {
+        use super::ContextKey;
+
+        static WIDTH: ContextKey<i32> = ContextKey::new("width");
+        static HEIGHT: ContextKey<i32> = ContextKey::new("height");
+
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        hook.provide_context_keyed(&WIDTH, 80);
+        hook.provide_context_keyed(&HEIGHT, 24);
+        hook.provide_context(0i32);
+
+        let size = |hook: &mut super::Hook| {
+            let width = hook.use_context_keyed(&WIDTH);
+            (width, hook.use_context_keyed(&HEIGHT))
+        };
+        assert_eq!(
+            hook.with_child(|hook| hook.with_child(size)),
+            (Some(80), Some(24))
+        );
+
+        let shadowed = hook.with_child(|hook| {
+            hook.provide_context_keyed(&WIDTH, 40);
+            hook.with_child(size)
+        });
+        assert_eq!(shadowed, (Some(40), Some(24)));
+        assert_eq!(hook.use_context::<i32>(), Some(0));
+        assert_eq!(
+            hook.use_context_keyed(&ContextKey::<i32>::new("depth")),
+            None
+        );
+    }
+
+    #[test]
+    fn first_render() {
+        let rt = crate::Runtime::new();
+        let render = || {
+            let mut hook = rt.hook();
+            let first = hook.is_first_render();
+            let (value, _) = hook.use_state("after");
+            (first, value, hook.hooks_used())
+        };
+
+        assert_eq!(render(), (true, "after", 2));
+        assert_eq!(render(), (false, "after", 2));
+        assert_eq!(render(), (false, "after", 2));
+    }
+
+    #[test]
+    fn enter_child() {
+        let rt = crate::Runtime::new();
+        let render = || {
+            let mut hook = rt.hook();
+            let (_, _) = hook.use_state("root");
+
+            let mut outer = hook.enter_child();
+            let (outer_value, set_outer) = outer.use_state(1);
+            set_outer(outer_value + 1);
+
+            let inner = {
+                let mut inner = outer.enter_child();
+                let (value, set) = inner.use_state(10);
+                set(value + 10);
+                value
+            };
+            drop(outer);
+
+            let (sibling, _) = hook.enter_child().use_state("sibling");
+            assert_eq!(hook.end_render(), Ok(()));
+            (outer_value, inner, sibling)
+        };
+
+        assert_eq!(render(), (1, 10, "sibling"));
+        assert_eq!(render(), (2, 20, "sibling"));
+        assert_eq!(rt.peek(&[0, 0], 0), Some(30));
+        assert_eq!(rt.peek::<&str>(&[1], 0), Some("sibling"));
+
+        // the hook count of a child gets checked once its scope ends
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state("root");
+        drop(hook.enter_child());
+        assert!(hook.end_render().is_err());
+    }
+
+    #[test]
+    fn memo_arc() {
+        /// doesn't implement Clone
+        struct Table(Vec<u64>);
+
+        let rt = crate::Runtime::new();
+        let computed = Arc::new(Mutex::new(0));
+        let render = |deps: u8| {
+            let computed = computed.clone();
+            rt.hook().use_memo_arc(deps, move || {
+                *computed.lock().unwrap() += 1;
+                Table(vec![0; 1 << 16])
+            })
+        };
+
+        let first = render(1);
+        let second = render(1);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(*computed.lock().unwrap(), 1);
+
+        let third = render(2);
+        assert!(!Arc::ptr_eq(&second, &third));
+        assert_eq!(third.0.len(), 1 << 16);
+        assert_eq!(*computed.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn callback() {
+        let rt = crate::Runtime::new();
+        let render = |deps: i32| rt.hook().use_callback(deps, move |x: i32| x + deps);
+
+        let first = render(1);
+        let second = render(1);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(second(1), 2);
+
+        let third = render(2);
+        assert!(!Arc::ptr_eq(&second, &third));
+    }
+
+    #[test]
+    fn previous() {
+        let rt = crate::Runtime::new();
+        let render = |value: i32| {
+            let mut hook = rt.hook();
+            let previous = hook.use_previous(value);
+            (previous, hook.use_state("next").0)
+        };
+
+        assert_eq!(render(1), (None, "next"));
+        assert_eq!(render(2), (Some(1), "next"));
+        assert_eq!(render(3), (Some(2), "next"));
+    }
+
+    #[cfg(not(feature = "thread-local"))]
+    #[test]
+    fn async_reducer() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        fn push(mut log: Vec<(u8, i32)>, action: (u8, i32)) -> Vec<(u8, i32)> {
+            log.push(action);
+            log
+        }
+
+        let rt = crate::Runtime::new();
+        let read = || rt.hook().use_reducer_async(Vec::new(), push).0;
+
+        let (_, dispatch) = rt.hook().use_reducer_async(Vec::new(), push);
+
+        // the worker applies actions on its own and notifies subscribers
+        let (notify, notified) = mpsc::channel();
+        rt.subscribe(move || {
+            let _ = notify.send(());
+        });
+        dispatch.dispatch((0, 0));
+        notified
+            .recv_timeout(Duration::from_secs(5))
+            .expect("worker to apply the action");
+        assert_eq!(read(), [(0, 0)]);
+
+        let threads: Vec<_> = (1..=2)
+            .map(|thread| {
+                let dispatch = dispatch.clone();
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        dispatch.dispatch((thread, i));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        dispatch.drain();
+        let log = read();
+        assert_eq!(log.len(), 101);
+        for thread in 1..=2 {
+            let actions: Vec<_> = log
+                .iter()
+                .filter(|(t, _)| *t == thread)
+                .map(|(_, i)| *i)
+                .collect();
+            assert_eq!(actions, (0..50).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn conditional_state() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let mut render = |show: bool| {
+            hook.begin_render();
+            let (before, set_before) = hook.use_state(0);
+            set_before(before + 1);
+
+            let mut optional = None;
+            if show {
+                let (value, set) = hook.use_state(String::from("a"));
+                set(value.clone() + "a");
+                optional = Some(value);
+            }
+
+            let (after, set_after) = hook.use_state(0u32);
+            set_after(after + 100);
+            (before, optional, after)
+        };
+
+        assert_eq!(render(false), (0, None, 0));
+        assert_eq!(render(true), (1, Some("a".to_string()), 100));
+        assert_eq!(render(false), (2, None, 200));
+        assert_eq!(render(true), (3, Some("aa".to_string()), 300));
+
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(0);
+        assert_eq!(hook.use_state(String::new()).0, "aaa");
+    }
+
+    #[test]
+    fn conditional_state_dropped() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let mut render = |show: bool| {
+            hook.begin_render();
+            let mut optional = None;
+            if show {
+                let (value, set) = hook.use_state(String::from("a"));
+                set(value.clone() + "a");
+                optional = Some(value);
+            }
+
+            let (count, set_count) = hook.use_state(0u32);
+            set_count(count + 1);
+            let same_hooks = hook.end_render().is_ok();
+            (optional, count, same_hooks)
+        };
+
+        assert_eq!(render(true), (Some("a".to_string()), 0, true));
+        assert_eq!(render(true), (Some("aa".to_string()), 1, true));
+        // skipping the hook drops its state, but leaves the one of the hook after it alone
+        assert_eq!(render(false), (None, 2, false));
+        assert_eq!(render(true), (Some("a".to_string()), 3, true));
+        assert_eq!(rt.lock().get_state(&[]).read().len(), 2);
+    }
+
+    #[test]
+    fn conditional_state_missing_first() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let mut render = |show: bool| {
+            hook.begin_render();
+            let mut optional = None;
+            if show {
+                let (value, set) = hook.use_state(String::from("a"));
+                set(value.clone() + "a");
+                optional = Some(value);
+            }
+
+            let (count, set_count) = hook.use_state(0u8);
+            set_count(count + 1);
+            drop(hook.end_render());
+            (optional, count)
+        };
+
+        assert_eq!(render(false), (None, 0));
+        // the hook showing up doesn't take over the register of the one after it
+        assert_eq!(render(true), (Some("a".to_string()), 1));
+        assert_eq!(render(true), (Some("aa".to_string()), 2));
+        assert_eq!(render(false), (None, 3));
+        assert_eq!(render(true), (Some("a".to_string()), 4));
+        assert_eq!(rt.lock().get_state(&[]).read().len(), 2);
+    }
+
+    #[test]
+    fn id() {
+        let rt = crate::Runtime::new();
+        let render = || {
+            let mut hook = rt.hook();
+            (hook.use_id(), hook.use_id())
+        };
+
+        let (a, b) = render();
+        assert_ne!(a, b);
+        assert!(a.starts_with("act-"));
+        assert_eq!(render(), (a, b));
+    }
+
+    #[test]
+    fn inline_cursor() {
+        use std::time::{Duration, Instant};
+
+        let rt = crate::Runtime::new();
+        let start = Instant::now();
+
+        rt.hook().with_child(|hook| {
+            hook.with_child(|hook| {
+                for _ in 0..1000 {
+                    let (_, set) = hook.use_state(0);
+                    // setters copy the cursor without touching the heap
+                    assert!(!set.register.cursor.spilled());
+                }
+            })
+        });
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn deep_cursor() {
+        fn nest(hook: &mut super::Hook, depth: usize) -> (i32, super::Setter<i32>) {
+            match depth {
+                0 => hook.use_state(0),
+                _ => hook.with_child(|hook| nest(hook, depth - 1)),
+            }
+        }
+
+        let rt = crate::Runtime::new();
+        let (_, set) = nest(&mut rt.hook(), 12);
+        assert!(set.register.cursor.spilled());
+        set(12);
+
+        let (value, _) = nest(&mut rt.hook(), 12);
+        assert_eq!(value, 12);
+    }
+
+    #[test]
+    fn copy_state() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let (_, set_number) = hook.use_state_copy(1i32);
+        let (_, set_flag) = hook.use_state_copy(false);
+        let (_, _) = hook.use_state_copy([0u64; 4]);
+        set_number(-7);
+        assert!(!set_flag.replace(true));
+
+        let mut hook = rt.hook();
+        assert_eq!(hook.use_state_copy(0i32).0, -7);
+        assert!(hook.use_state_copy(false).0);
+
+        {
+            let tree = rt.lock();
+            let registers = tree.get_state(&[]).read();
+            assert!(registers.get(0).unwrap().is_inline());
+            assert!(registers.get(1).unwrap().is_inline());
+            // too big to be stored inline
+            assert!(!registers.get(2).unwrap().is_inline());
+        }
+
+        // reading inline values as some other type gets caught
+        let mut hook = rt.hook();
+        assert_eq!(
+            hook.try_use_state(0u32).err(),
+            Some(super::HookError::TypeMismatch {
+                index: 0,
+                expected: "u32",
+                found: Some("i32")
+            })
+        );
+    }
+
+    #[test]
+    fn toggle() {
+        let rt = crate::Runtime::new();
+
+        let (value, toggle) = rt.hook().use_toggle(false);
+        assert!(!value);
+        toggle();
+        assert!(rt.hook().use_toggle(false).0);
+        toggle();
+        assert!(!rt.hook().use_toggle(false).0);
+
+        // every flip sees the one before, even when deferred
+        rt.batch(|| {
+            toggle();
+            toggle();
+            toggle();
+        });
+        assert!(rt.hook().use_toggle(false).0);
+    }
+
+    #[test]
+    fn counter() {
+        let rt = crate::Runtime::new();
+        let read = || rt.hook().use_counter(0).0;
+
+        let (count, actions) = rt.hook().use_counter(5);
+        assert_eq!(count, 5);
+        actions.inc();
+        actions.inc();
+        actions.dec();
+        assert_eq!(read(), 6);
+        actions.add(-10);
+        assert_eq!(read(), -4);
+        actions.reset();
+        assert_eq!(read(), 5);
+
+        #[cfg(not(feature = "thread-local"))]
+        {
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    let actions = actions.clone();
+                    std::thread::spawn(move || (0..100).for_each(|_| actions.inc()))
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert_eq!(read(), 405);
+        }
+    }
+
+    #[test]
+    fn debounce() {
+        let clock = crate::TestClock::new();
+        let rt = crate::Runtime::with_clock(clock.clone());
+        let delay = Duration::from_millis(300);
+        let render = |value: &'static str| rt.hook().use_debounce(value, delay);
+
+        let wakes = Arc::new(Mutex::new(0));
+        {
+            let wakes = wakes.clone();
+            rt.subscribe(move || *wakes.lock().unwrap() += 1);
+        }
+
+        assert_eq!(render("a"), "a");
+        assert_eq!(render("ab"), "a");
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(render("abc"), "a");
+        // the wake for "ab" is due, but "abc" came in since
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(render("abc"), "a");
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(*wakes.lock().unwrap(), 2);
+        assert!(rt.take_dirty());
+        assert_eq!(render("abc"), "abc");
+    }
+
+    #[test]
+    fn interval() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let clock = crate::TestClock::new();
+        let rt = crate::Runtime::with_clock(clock.clone());
+        let period = Duration::from_secs(1);
+
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let render = |period| {
+            rt.hook().with_child(|hook| {
+                let ticks = ticks.clone();
+                hook.use_interval(period, move || {
+                    ticks.fetch_add(1, Ordering::SeqCst);
+                });
+            })
+        };
+
+        render(period);
+        render(period);
+        for _ in 0..3 {
+            clock.advance(period);
+        }
+        assert_eq!(ticks.load(Ordering::SeqCst), 3);
+
+        // the old interval stops, the new one starts from now on
+        render(period * 2);
+        clock.advance(period);
+        assert_eq!(ticks.load(Ordering::SeqCst), 3);
+        clock.advance(period);
+        assert_eq!(ticks.load(Ordering::SeqCst), 4);
+
+        rt.hook().unmount_child(0);
+        clock.advance(period * 10);
+        assert_eq!(ticks.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn interval_catches_up() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let clock = crate::TestClock::new();
+        let rt = crate::Runtime::with_clock(clock.clone());
+        let period = Duration::from_secs(1);
+
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counter = ticks.clone();
+        rt.hook().use_interval(period, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        clock.advance(period * 3);
+        assert_eq!(ticks.load(Ordering::SeqCst), 3);
+        clock.advance(period / 2);
+        assert_eq!(ticks.load(Ordering::SeqCst), 3);
+        clock.advance(period / 2);
+        assert_eq!(ticks.load(Ordering::SeqCst), 4);
+    }
+
+    #[cfg(feature = "thread-local")]
+    #[test]
+    fn interval_system_clock() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let rt = crate::Runtime::new();
+        let period = Duration::from_millis(1);
+
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counter = ticks.clone();
+        rt.hook().use_interval(period, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        // ticks wait for the thread to run them
+        std::thread::sleep(period * 5);
+        assert_eq!(ticks.load(Ordering::SeqCst), 0);
+        assert!(crate::SystemClock::run_due().is_some());
+        assert_eq!(ticks.load(Ordering::SeqCst), 1);
+
+        std::thread::sleep(period * 5);
+        crate::SystemClock::run_due();
+        assert_eq!(ticks.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn named_state() {
+        let rt = crate::Runtime::new();
+        let render = |show: bool| {
+            let mut hook = rt.hook();
+            hook.begin_render();
+
+            let (count, set_count) = hook.use_state(0);
+            let name = show.then(|| {
+                let (name, set_name) = hook.use_state_named("name", String::from("anonymous"));
+                set_name(format!("{name}!"));
+                name
+            });
+            set_count(count + 1);
+
+            hook.end_render().unwrap();
+            (count, name)
+        };
+
+        assert_eq!(render(true), (0, Some("anonymous".to_string())));
+        assert_eq!(render(false), (1, None));
+        assert_eq!(render(true), (2, Some("anonymous!".to_string())));
+    }
+
+    #[test]
+    fn keyed_state() {
+        let rt = crate::Runtime::new();
+        let render = |show: bool| {
+            let mut hook = rt.hook();
+            hook.begin_render();
+
+            let (count, set_count) = hook.use_state(0);
+            let item = show.then(|| {
+                let (item, set_item) = hook.use_state_keyed(("item", 7), 0u32);
+                set_item(item + 10);
+                item
+            });
+            set_count(count + 1);
+
+            hook.end_render().unwrap();
+            (count, item)
+        };
+
+        assert_eq!(render(true), (0, Some(0)));
+        assert_eq!(render(false), (1, None));
+        assert_eq!(render(true), (2, Some(10)));
+
+        // an equal key of the same type must not alias a register of a different type
+        let mut hook = rt.hook();
+        assert_eq!(
+            hook.try_use_state_keyed(("item", 7), "text").err(),
+            Some(super::HookError::KeyMismatch {
+                expected: "&str",
+                found: "u32"
+            })
+        );
+        // keys of different types don't collide
+        assert_eq!(hook.use_state_keyed(7, "text").0, "text");
+    }
+
+    #[test]
+    fn async_state() {
+        use super::AsyncState;
+
+        let rt = crate::Runtime::with_executor(crate::BlockingExecutor);
+        let mut hook = rt.hook();
+        let ready = hook.use_async((), || async { Ok::<_, ()>(42) });
+        let failed = hook.use_async((), || async { Err::<(), _>("offline") });
+
+        assert_eq!(ready, AsyncState::Ready(42));
+        assert_eq!(failed, AsyncState::Failed("offline"));
+    }
+
+    #[cfg(all(feature = "tokio", not(feature = "thread-local")))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tokio_async_state() {
+        use super::AsyncState;
+
+        let rt = crate::Runtime::with_executor(crate::TokioExecutor::current());
+        let render = || {
+            rt.hook().use_async((), || async {
+                tokio::task::yield_now().await;
+                Ok::<_, ()>(42)
+            })
+        };
+
+        let ready = async {
+            while render() == AsyncState::Loading {
+                rt.changed().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), ready)
+            .await
+            .unwrap();
+        assert_eq!(render(), AsyncState::Ready(42));
+    }
+
+    #[test]
+    fn stale_async_results() {
+        use super::AsyncState;
+        use crate::{Executor, Task};
+
+        /// keeps tasks around, so the test decides when they complete
+        #[derive(Clone, Default)]
+        struct Deferred(Arc<Mutex<Vec<Task>>>);
+
+        impl Executor for Deferred {
+            fn spawn(&self, task: Task) {
+                self.0.lock().unwrap().push(task);
+            }
+        }
+
+        let executor = Deferred::default();
+        let rt = crate::Runtime::with_executor(executor.clone());
+        let render = |query: &'static str| {
+            rt.hook()
+                .use_async(query, || async move { Ok::<_, ()>(query.len()) })
+        };
+
+        assert_eq!(render("a"), AsyncState::Loading);
+        assert_eq!(render("abc"), AsyncState::Loading);
+
+        // the future for "a" completes last, but "abc" is what got asked for by now
+        let mut tasks = std::mem::take(&mut *executor.0.lock().unwrap());
+        crate::executor::block_on(tasks.pop().unwrap());
+        crate::executor::block_on(tasks.pop().unwrap());
+        assert_eq!(render("abc"), AsyncState::Ready(3));
+    }
+
+    #[test]
+    fn memo_cleanup() {
+        let rt = crate::Runtime::new();
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let render = |path: &'static str| {
+            rt.hook().with_child(|hook| {
+                let closed = closed.clone();
+                hook.use_memo_cleanup(
+                    path,
+                    || format!("handle to {path}"),
+                    move |handle: &String| closed.lock().unwrap().push(handle.clone()),
+                )
+            })
+        };
+
+        render("a");
+        render("a");
+        assert!(closed.lock().unwrap().is_empty());
+
+        assert_eq!(render("b"), "handle to b");
+        render("b");
+        assert_eq!(*closed.lock().unwrap(), ["handle to a"]);
+
+        rt.hook().unmount_child(0);
+        assert_eq!(*closed.lock().unwrap(), ["handle to a", "handle to b"]);
+    }
+
+    #[test]
+    fn read_state() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        struct Bytes(Vec<u8>);
+
+        impl Clone for Bytes {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::SeqCst);
+                Bytes(self.0.clone())
+            }
+        }
+
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(0);
+        let (_, _) = hook.use_state(Bytes(vec![0; 4096]));
+        let clones = CLONES.load(Ordering::SeqCst);
+
+        assert_eq!(hook.read_state(1, |bytes: &Bytes| bytes.0.len()), 4096);
+        assert_eq!(CLONES.load(Ordering::SeqCst), clones);
+
+        let bytes = hook.read_guard::<Bytes>(1).unwrap();
+        assert_eq!(bytes.0.len(), 4096);
+        assert!(bytes.0.iter().all(|&byte| byte == 0));
+        drop(bytes);
+        assert_eq!(CLONES.load(Ordering::SeqCst), clones);
+
+        assert!(hook.read_guard::<String>(1).is_none());
+        assert!(hook.read_guard::<Bytes>(2).is_none());
+    }
+
+    #[test]
+    fn read_guard_one_register() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(vec![7u8; 1 << 20]);
+        let (_, set) = hook.use_state(0);
+
+        // only the register behind the guard stays locked, other state can still be used
+        let bytes = hook.read_guard::<Vec<u8>>(0).unwrap();
+        set(1);
+        assert_eq!(hook.read_state(1, |count: &i32| *count), 1);
+        assert_eq!(bytes.len(), 1 << 20);
+        assert_eq!(bytes[1234], 7);
+    }
+
+    #[cfg(not(feature = "thread-local"))]
+    #[test]
+    fn read_guard_blocks_writer() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let (_, set) = hook.use_state(0);
+
+        let count = hook.read_guard::<i32>(0).unwrap();
+        let writer = std::thread::spawn(move || set(1));
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(*count, 0);
+
+        drop(count);
+        writer.join().unwrap();
+        assert_eq!(hook.read_state(0, |count: &i32| *count), 1);
+    }
+
+    #[test]
+    fn error_boundary() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+
+        let error = hook
+            .use_error_boundary(|child| {
+                let (_, set) = child.use_state(1);
+                set(2);
+                let _tree = child.runtime.lock();
+                panic!("lost connection");
+            })
+            .unwrap_err();
+        assert_eq!(error.path, [0]);
+        assert_eq!(error.message, "lost connection");
+        assert!(rt.try_lock().is_ok());
+
+        let (value, _) = hook.with_child(|sibling| sibling.use_state("sibling"));
+        assert_eq!(value, "sibling");
+        assert!(hook.use_error_boundary(|_| ()).is_ok());
+
+        // the state of the child that panicked starts out fresh
+        let mut hook = rt.hook();
+        hook.use_error_boundary(|child| assert_eq!(child.use_state(1).0, 1))
+            .unwrap();
+    }
+
+    #[cfg(not(feature = "thread-local"))]
+    #[test]
+    fn compare_and_set() {
+        let rt = crate::Runtime::new();
+        let (_, set) = rt.hook().use_state(0usize);
+        let wins = Mutex::new(vec![0; 100]);
+
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for expected in 0..100 {
+                        loop {
+                            if set.compare_and_set(expected, expected + 1) {
+                                wins.lock().unwrap()[expected] += 1;
+                            }
+                            if rt.hook().use_state(0usize).0 > expected {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(rt.hook().use_state(0usize).0, 100);
+        assert!(wins.into_inner().unwrap().iter().all(|&wins| wins == 1));
+        assert!(!set.compare_and_set(0, 1));
+    }
+
+    #[test]
+    fn dyn_state() {
+        type Handler = dyn Fn(i32) -> i32 + Send + Sync;
+
+        let rt = crate::Runtime::new();
+        let offset = 10;
+        let render = || {
+            let mut hook = rt.hook();
+            let (double, _) = hook.use_state_dyn::<Handler>(Arc::new(|x| x * 2));
+            let (add, set_add) = hook.use_state_dyn::<Handler>(Arc::new(move |x| x + offset));
+            (double, add, set_add)
+        };
+
+        let (double, add, set_add) = render();
+        assert_eq!((double(3), add(3)), (6, 13));
+
+        set_add(Arc::new(|x| x - 1));
+        let (double, add, _) = render();
+        assert_eq!((double(3), add(3)), (6, 2));
+    }
+
+    #[test]
+    fn external_store() {
+        use super::{StoreChanged, Unsubscribe};
+        use crate::sync::Shared;
+
+        /// some state the runtime doesn't know about
+        #[derive(Default)]
+        struct Source {
+            value: Mutex<i32>,
+            listeners: Mutex<Vec<StoreChanged>>,
+        }
+
+        impl Source {
+            fn set(&self, value: i32) {
+                *self.value.lock().unwrap() = value;
+                for listener in self.listeners.lock().unwrap().iter() {
+                    listener();
+                }
+            }
+        }
+
+        let source = Shared::new(Source::default());
+        let rt = crate::Runtime::new();
+        let render = || {
+            rt.hook().with_child(|hook| {
+                let subscribe = {
+                    let source = source.clone();
+                    move |changed| -> Unsubscribe {
+                        source.listeners.lock().unwrap().push(changed);
+                        Box::new(move || source.listeners.lock().unwrap().clear())
+                    }
+                };
+                let snapshot = {
+                    let source = source.clone();
+                    move || *source.value.lock().unwrap()
+                };
+                hook.use_sync_external_store(subscribe, snapshot)
+            })
+        };
+
+        let notified = Arc::new(Mutex::new(0));
+        {
+            let notified = notified.clone();
+            rt.subscribe(move || *notified.lock().unwrap() += 1);
+        }
+
+        assert_eq!(render(), 0);
+        assert_eq!(render(), 0);
+        assert_eq!(source.listeners.lock().unwrap().len(), 1);
+
+        source.set(5);
+        assert_eq!(*notified.lock().unwrap(), 1);
+        assert_eq!(render(), 5);
+
+        // unchanged snapshots don't notify
+        source.set(5);
+        assert_eq!(*notified.lock().unwrap(), 1);
+
+        rt.hook().unmount_child(0);
+        assert!(source.listeners.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn update_with() {
+        #[derive(Clone)]
+        struct Form {
+            name: String,
+            tags: Vec<String>,
+            age: u32,
+        }
+
+        let rt = crate::Runtime::new();
+        let form = Form {
+            name: "ada".to_string(),
+            tags: vec!["admin".to_string(); 100],
+            age: 36,
+        };
+        let (_, set) = rt.hook().use_state(form);
+        rt.take_dirty();
+
+        set.update_with(|form| form.age += 1);
+        assert!(rt.take_dirty());
+
+        let (form, _) = rt.hook().use_state(Form {
+            name: String::new(),
+            tags: Vec::new(),
+            age: 0,
+        });
+        assert_eq!(form.age, 37);
+        assert_eq!(form.name, "ada");
+        assert_eq!(form.tags.len(), 100);
+    }
+
+    #[test]
+    fn last_seq() {
+        let rt = crate::Runtime::new();
+        let (_, set) = rt.hook().use_state(0);
+        let (_, other) = rt.hook().use_state(0);
+        let before = set.last_seq();
+
+        set(1);
+        other.call(2);
+        assert_eq!(set.last_seq(), before + 2);
+        assert_eq!(other.replace(3), 2);
+        assert_eq!(set.last_seq(), before + 3);
+
+        // the register at the same position after reset isn't the one of set
+        rt.reset();
+        assert_eq!(set.last_seq(), 0);
+        rt.hook().use_state(0).1(1);
+        assert_eq!(set.last_seq(), 0);
+
+        let (_, child) = rt.hook().with_child(|hook| hook.use_state(0));
+        rt.reset();
+        assert_eq!(child.last_seq(), 0);
+    }
+
+    #[test]
+    fn stale_setter() {
+        let rt = crate::Runtime::new();
+        let (_, set) = rt.hook().use_state(0);
+        let (_, child) = rt.hook().with_child(|hook| hook.use_state("child"));
+        assert_eq!(set.try_set(1), Ok(()));
+        assert_eq!(rt.hook().use_state(0).0, 1);
+
+        rt.reset();
+        let (_, _) = rt.hook().use_state(10);
+        assert_eq!(
+            set.try_set(2),
+            Err(super::HookError::StaleSetter { node: vec![] })
+        );
+        assert_eq!(rt.hook().use_state(0).0, 10);
+
+        // the child doesn't even exist anymore
+        assert!(child.try_set("changed").is_err());
+    }
+
+    #[test]
+    fn force_update() {
+        let rt = crate::Runtime::new();
+        let notified = Arc::new(Mutex::new(0));
+        {
+            let notified = notified.clone();
+            rt.subscribe(move || *notified.lock().unwrap() += 1);
+        }
+
+        let force_update = rt.hook().use_force_update();
+        rt.take_dirty();
+        force_update();
+        force_update();
+        assert_eq!(*notified.lock().unwrap(), 2);
+        assert!(rt.take_dirty());
+    }
+
+    #[test]
+    fn path() {
+        use super::Path;
+
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        assert_eq!(Path(hook.path()).to_string(), "/");
+
+        hook.with_child(|_| ());
+        let path = hook.with_child(|hook| {
+            hook.with_child(|_| ());
+            hook.with_child(|_| ());
+            hook.with_child(|hook| hook.path().to_vec())
+        });
+        assert_eq!(path, [1, 2]);
+        assert_eq!(Path(&path).to_string(), "1/2");
+    }
+
+    #[test]
+    fn selector() {
+        #[derive(Clone)]
+        struct Todos {
+            items: Vec<&'static str>,
+            filter: &'static str,
+        }
+
+        fn reducer(mut todos: Todos, action: (&'static str, &'static str)) -> Todos {
+            match action {
+                ("add", item) => todos.items.push(item),
+                (_, filter) => todos.filter = filter,
+            }
+            todos
+        }
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let rt = crate::Runtime::new();
+        let selected = AtomicUsize::new(0);
+        let render = || {
+            let mut hook = rt.hook();
+            let init = Todos {
+                items: Vec::new(),
+                filter: "all",
+            };
+            // another state of the same type doesn't get in the way
+            let (_, _) = hook.use_state(init.clone());
+            let (_, dispatch) = hook.use_reducer(init, reducer);
+            let count = hook.use_selector(1, |todos: &Todos| {
+                selected.fetch_add(1, Ordering::SeqCst);
+                todos.items.len()
+            });
+            (count, dispatch)
+        };
+
+        let (count, dispatch) = render();
+        assert_eq!(count, (0, true));
+        dispatch(("add", "milk"));
+        assert_eq!(render().0, (1, true));
+
+        dispatch(("filter", "done"));
+        assert_eq!(render().0, (1, false));
+        assert_eq!(selected.load(Ordering::SeqCst), 3);
+
+        // nothing got written, so there is nothing to select
+        assert_eq!(render().0, (1, false));
+        assert_eq!(selected.load(Ordering::SeqCst), 3);
+    }
+}
+
+/// register content of use_effect. Dropping it runs the cleanup of the last effect, so cleanups
+/// also get run once the StateTree node holding them goes away.
+struct Effect<D> {
+    deps: D,
+    cleanup: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<D> Drop for Effect<D> {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.cleanup.take() {
+            cleanup();
+        }
+    }
+}
+
+/// replaces the value of register `index` with the outcome of `reducer`, unless the register
+/// isn't the one of `generation` anymore
+#[cfg(not(feature = "thread-local"))]
+fn reduce<S, A>(
+    state: &State,
+    index: usize,
+    generation: Generation,
+    reducer: fn(S, A) -> S,
+    action: A,
+) where
+    S: 'static + Clone + MaybeSend,
+{
+    let mut registers = state.write();
+    let Some(mut register) = registers.current_mut(index, generation) else {
+        return;
+    };
+
+    let current = register
+        .get::<S>(index)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .clone();
+
+    register.overwrite(Register::new(reducer(current, action)));
+}
+
+/// dispatch function of use_reducer_async. Actions get applied by a background thread, which
+/// writes them to the state and notifies subscribers. The thread stops once all dispatchers of
+/// the hook are gone.
+#[cfg(not(feature = "thread-local"))]
+pub struct Dispatcher<A> {
+    actions: mpsc::Sender<A>,
+    /// receives a message for every action, so the worker knows when there is work to do
+    wake: mpsc::Sender<()>,
+    /// locked while applying an action, so actions get applied in order even if drain runs
+    /// alongside the worker
+    queue: Arc<Mutex<mpsc::Receiver<A>>>,
+    apply: Arc<dyn Fn(A) + Send + Sync>,
+}
+
+#[cfg(not(feature = "thread-local"))]
+impl<A> Dispatcher<A>
+where
+    A: 'static + Send,
+{
+    fn spawn<S>(
+        runtime: WeakRuntime,
+        cursor: Cursor,
+        (index, generation): (usize, Generation),
+        reducer: fn(S, A) -> S,
+    ) -> Self
+    where
+        S: 'static + Clone + Send,
+    {
+        let (actions, queue) = mpsc::channel();
+        let (wake, woken) = mpsc::channel::<()>();
+
+        let apply = move |action: A| {
+            // nothing to do if the state is gone already
+            if let Some(runtime) = runtime.upgrade() {
+                runtime.write(&cursor, Some(index), move |state| {
+                    reduce(state, index, generation, reducer, action)
+                });
+            }
+        };
+
+        let dispatcher = Dispatcher {
+            actions,
+            wake,
+            queue: Arc::new(Mutex::new(queue)),
+            apply: Arc::new(apply),
+        };
+
+        let (queue, apply) = (dispatcher.queue.clone(), dispatcher.apply.clone());
+        std::thread::spawn(move || {
+            for () in woken {
+                let queue = queue.lock().unwrap_or_else(|e| e.into_inner());
+                // drain might have gotten to the action first
+                if let Ok(action) = queue.try_recv() {
+                    apply(action);
+                }
+            }
+        });
+
+        dispatcher
+    }
+
+    /// queues `action` to be applied by the background thread
+    pub fn dispatch(&self, action: A) {
+        // the receivers live as long as the dispatcher, so sending can't fail
+        let _ = self.actions.send(action);
+        let _ = self.wake.send(());
+    }
+
+    /// applies all queued actions on the calling thread right away, instead of waiting for the
+    /// background thread
+    pub fn drain(&self) {
+        let queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        while let Ok(action) = queue.try_recv() {
+            (self.apply)(action);
+        }
+    }
+}
+
+#[cfg(not(feature = "thread-local"))]
+impl<A> Clone for Dispatcher<A> {
+    fn clone(&self) -> Self {
+        Dispatcher {
+            actions: self.actions.clone(),
+            wake: self.wake.clone(),
+            queue: self.queue.clone(),
+            apply: self.apply.clone(),
+        }
+    }
+}
+
+/// register content of use_selector, along with the register it got selected from
+#[derive(Clone)]
+struct Selection<R> {
+    /// generation of the source register, so one taking over its slot doesn't go unnoticed
+    slot: Generation,
+    /// Register::seq of the source when `value` got selected
+    seq: u64,
+    value: R,
+}
+
+/// register content of use_interval, which stops the interval once dropped
+struct Interval {
+    period: Duration,
+    cancelled: Shared<AtomicBool>,
+}
+
+impl Drop for Interval {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// calls `f` once `period` passed, and keeps doing so until `cancelled` gets set
+fn tick<F>(clock: Shared<dyn Clock>, period: Duration, cancelled: Shared<AtomicBool>, f: F)
+where
+    F: 'static + Fn() + MaybeSend,
+{
+    let next = clock.clone();
+    let wake = move || {
+        if cancelled.load(Ordering::SeqCst) {
+            return;
+        }
+
+        f();
+        tick(next, period, cancelled, f);
+    };
+
+    clock.schedule(period, Box::new(wake));
+}
+
+/// what use_async knows about its future
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsyncState<T, E> {
+    /// the future didn't complete yet
+    Loading,
+    Ready(T),
+    Failed(E),
+}
+
+/// register content of use_async
+struct AsyncTask<D, T, E> {
+    deps: D,
+    state: AsyncState<T, E>,
+    /// id of the latest future, so results of those spawned for older deps get ignored
+    id: usize,
+}
+
+/// ids of the futures spawned by use_async
+static TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// stores the result of the future `id` of use_async in register `index`, unless it got
+/// superseded by another future or unmounted in the meantime
+fn complete<D, T, E>(
+    runtime: &Runtime,
+    cursor: &[usize],
+    index: usize,
+    id: usize,
+    result: Result<T, E>,
+) where
+    D: 'static,
+    T: 'static,
+    E: 'static,
+{
+    {
+        let tree = runtime.lock();
+        let Ok(state) = tree.try_get_state(cursor) else {
+            return;
+        };
+
+        let mut registers = state.write();
+        let mut register = registers.get_mut(index);
+        match register
+            .as_deref_mut()
+            .and_then(|register| register.downcast_mut::<AsyncTask<D, T, E>>())
+        {
+            Some(task) if task.id == id => {
+                task.state = match result {
+                    Ok(value) => AsyncState::Ready(value),
+                    Err(e) => AsyncState::Failed(e),
+                };
+            }
+            _ => return,
+        }
+
+        state.mark_dirty();
+    }
+
+    runtime.notify();
+}
+
+/// register content of use_memo_cleanup, which passes the value to the cleanup once dropped
+struct MemoCleanup<D, T> {
+    deps: D,
+    value: T,
+    cleanup: Box<dyn Fn(&T) + Send>,
+}
+
+impl<D, T> Drop for MemoCleanup<D, T> {
+    fn drop(&mut self) {
+        (self.cleanup)(&self.value);
+    }
+}
+
+/// passed to the subscribe function of use_sync_external_store, to be called whenever the
+/// external store changed
+#[cfg(not(feature = "thread-local"))]
+pub type StoreChanged = Box<dyn Fn() + Send>;
+
+#[cfg(feature = "thread-local")]
+pub type StoreChanged = Box<dyn Fn()>;
+
+/// returned by the subscribe function of use_sync_external_store, stops calling the
+/// StoreChanged it got passed
+#[cfg(not(feature = "thread-local"))]
+pub type Unsubscribe = Box<dyn FnOnce() + Send>;
+
+#[cfg(feature = "thread-local")]
+pub type Unsubscribe = Box<dyn FnOnce()>;
+
+/// register content of use_sync_external_store, which unsubscribes once dropped
+struct ExternalStore<T> {
+    snapshot: T,
+    unsubscribe: Option<Unsubscribe>,
+}
+
+impl<T> Drop for ExternalStore<T> {
+    fn drop(&mut self) {
+        if let Some(unsubscribe) = self.unsubscribe.take() {
+            unsubscribe();
+        }
+    }
+}
+
+/// stores `snapshot` in register `index` of the State at `cursor`, returning whether it differs
+/// from the stored one
+fn store_snapshot<T>(tree: &StateTree, cursor: &[usize], index: usize, snapshot: &T) -> bool
+where
+    T: 'static + Clone + PartialEq,
+{
+    let Ok(state) = tree.try_get_state(cursor) else {
+        return false;
+    };
+
+    let mut registers = state.write();
+    let mut register = registers.get_mut(index);
+    match register
+        .as_deref_mut()
+        .and_then(|register| register.downcast_mut::<ExternalStore<T>>())
+    {
+        Some(store) if store.snapshot != *snapshot => {
+            store.snapshot = snapshot.clone();
+            state.mark_dirty();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// register content of use_debounce
+struct Debounce<T> {
+    /// the value returned by the hook
+    committed: T,
+    /// the latest value passed to the hook
+    pending: T,
+    /// when pending last changed
+    since: Instant,
+}
+
+/// identifies a value provided through Hook::provide_context_keyed, so several values of the
+/// same type can be told apart. Keys of the same type with the same name are the same key.
+///
+/// ```ignore
+/// static PRIMARY: ContextKey<Color> = ContextKey::new("primary");
+/// static ACCENT: ContextKey<Color> = ContextKey::new("accent");
+/// ```
+pub struct ContextKey<T>(PhantomData<fn() -> T>, &'static str);
+
+impl<T: 'static> ContextKey<T> {
+    pub const fn new(name: &'static str) -> Self {
+        ContextKey(PhantomData, name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.1
+    }
+
+    fn id(&self) -> ContextId {
+        (TypeId::of::<T>(), Some(self.1))
+    }
+}
+
+/// key of StateTree::contexts
+type ContextId = (TypeId, Option<&'static str>);
+
+/// a reference to the value of a register, which keeps just that register locked for reading,
+/// see Hook::read_guard
+#[cfg(all(feature = "parking_lot", not(feature = "thread-local")))]
+pub type StateRef<'a, T> = parking_lot::MappedRwLockReadGuard<'a, T>;
+
+/// a reference to the value of a register, which keeps just that register borrowed, see
+/// Hook::read_guard
+#[cfg(feature = "thread-local")]
+pub type StateRef<'a, T> = std::cell::Ref<'a, T>;
+
+/// a reference to the value of a register, which keeps just that register locked for reading,
+/// see Hook::read_guard
+#[cfg(not(any(feature = "parking_lot", feature = "thread-local")))]
+pub struct StateRef<'a, T> {
+    _register: ReadGuard<'a, Register>,
+    /// points into the locked register, which only gets written to once the guard is dropped
+    value: *const T,
+}
+
+#[cfg(not(any(feature = "parking_lot", feature = "thread-local")))]
+impl<T> Deref for StateRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: see StateRef::value
+        unsafe { &*self.value }
+    }
+}
+
+/// the value of a register locked by Hook::read_guard, if it's a `T`
+#[cfg(all(feature = "parking_lot", not(feature = "thread-local")))]
+fn map_register<T: 'static>(register: ReadGuard<'_, Register>) -> Option<StateRef<'_, T>> {
+    parking_lot::RwLockReadGuard::try_map(register, Register::downcast_ref::<T>).ok()
+}
+
+/// the value of a register borrowed by Hook::read_guard, if it's a `T`
+#[cfg(feature = "thread-local")]
+fn map_register<T: 'static>(register: ReadGuard<'_, Register>) -> Option<StateRef<'_, T>> {
+    std::cell::Ref::filter_map(register, Register::downcast_ref::<T>).ok()
+}
+
+/// the value of a register locked by Hook::read_guard, if it's a `T`
+#[cfg(not(any(feature = "parking_lot", feature = "thread-local")))]
+fn map_register<T: 'static>(register: ReadGuard<'_, Register>) -> Option<StateRef<'_, T>> {
+    let value: *const T = register.downcast_ref::<T>()?;
+    Some(StateRef {
+        _register: register,
+        value,
+    })
+}
+
+/// the hook of a child, which gets back to its parent once dropped, see Hook::enter_child
+pub struct ChildScope<'a> {
+    parent: &'a mut Hook,
+    child: Hook,
+}
+
+impl Deref for ChildScope<'_> {
+    type Target = Hook;
+
+    fn deref(&self) -> &Hook {
+        &self.child
+    }
+}
+
+impl DerefMut for ChildScope<'_> {
+    fn deref_mut(&mut self) -> &mut Hook {
+        &mut self.child
+    }
+}
+
+impl Drop for ChildScope<'_> {
+    fn drop(&mut self) {
+        self.parent.finish_child(&mut self.child);
+    }
+}
+
+/// register content of use_throttle
+struct Throttle<T> {
+    /// the value returned by the hook
+    emitted: T,
+    /// the latest value passed to the hook
+    pending: T,
+    /// when emitted last changed
+    since: Instant,
+    /// whether subscribers will be notified once the window opens
+    scheduled: bool,
+}
+
+/// register content of use_deferred_value
+struct Deferred<T> {
+    /// the value returned by the hook
+    shown: T,
+    /// whether an update to the latest value got scheduled
+    scheduled: bool,
+    /// set once the scheduled update is due
+    due: Shared<AtomicBool>,
+}
+
+/// setter of use_state_fn, which computes the new value from the current one
+pub struct Updater<T> {
+    handle: Handle,
+    _value: PhantomData<fn(T) -> T>,
+}
+
+impl<T> Updater<T>
+where
+    T: 'static + Clone + MaybeSend,
+{
+    /// replaces the stored value with `f(value)`. The StateTree stays locked while `f` runs, so
+    /// no other update can sneak in between, but `f` must not touch state itself. Like other
+    /// setters this gets deferred during Runtime::batch, and `f` sees the writes before it once
+    /// the batch gets applied.
+    pub fn update(&self, f: impl FnOnce(T) -> T + MaybeSend + 'static) {
+        let (index, slot) = (self.handle.index, self.handle.slot);
+
+        self.handle
+            .runtime
+            .write(&self.handle.cursor, Some(index), move |state| {
+                let mut registers = state.write();
+                let Some(mut register) = registers.current_mut(index, slot) else {
+                    return;
+                };
+
+                let current = register
+                    .get::<T>(index)
+                    .unwrap_or_else(|e| panic!("{}", e))
+                    .clone();
+
+                register.overwrite(Register::new(f(current)));
+            });
+    }
+}
+
+/// path from the root of the StateTree to some State. Most trees are shallow enough for it to
+/// not need the heap.
+type Cursor = SmallVec<[usize; 8]>;
+
+#[cfg(not(feature = "thread-local"))]
+type SetFn<T> = dyn Fn(T) + Send + Sync;
+
+#[cfg(feature = "thread-local")]
+type SetFn<T> = dyn Fn(T);
+
+/// the state register a Setter writes to. Writes to a register that got dropped since, along with
+/// the State it belonged to or by Hook::end_render, get ignored.
+struct Handle {
+    runtime: Runtime,
+    cursor: Cursor,
+    index: usize,
+    /// of the State the register belongs to
+    generation: Generation,
+    /// of the register, see Registers::current_mut
+    slot: Generation,
+}
+
+impl Handle {
+    /// fails if the State at the cursor, or the register in it, isn't the one this handle got
+    /// created for anymore
+    fn check(&self) -> Result<(), HookError> {
+        let tree = self.runtime.try_lock()?;
+        match tree.try_get_state(&self.cursor) {
+            Ok(state)
+                if state.generation == self.generation
+                    && state.read().generation(self.index) == Some(self.slot) =>
+            {
+                Ok(())
+            }
+            _ => Err(HookError::StaleSetter {
+                node: self.cursor.to_vec(),
+            }),
+        }
+    }
+
+    fn set(&self, register: Register) {
+        let (index, slot) = (self.index, self.slot);
+        #[cfg(feature = "history")]
+        let (history, recorded) = (self.runtime.clone(), self.cursor.to_vec());
+
+        self.runtime.write(&self.cursor, Some(index), move |state| {
+            #[cfg(feature = "history")]
+            let type_name = register.type_name;
+            let previous = match state.write().current_mut(index, slot) {
+                Some(mut current) => current.overwrite(register),
+                None => return,
+            };
+
+            #[cfg(feature = "history")]
+            history.record(recorded, index, type_name, previous);
+            #[cfg(not(feature = "history"))]
+            drop(previous);
+        });
+    }
+
+    /// changes the stored value in place. Like set, this gets deferred during Runtime::batch.
+    fn update<T: 'static>(&self, f: impl FnOnce(&mut T) + MaybeSend + 'static) {
+        let (index, slot) = (self.index, self.slot);
+
+        self.runtime.write(&self.cursor, Some(index), move |state| {
+            let mut registers = state.write();
+            let Some(mut register) = registers.current_mut(index, slot) else {
+                return;
+            };
+
+            register.seq += 1;
+            let value = register
+                .get_mut::<T>(index)
+                .unwrap_or_else(|e| panic!("{}", e));
+
+            f(value);
+        });
+    }
+
+    /// swaps in `register`, returning the value of the previous one
+    fn replace<T>(&self, register: Register) -> T
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        self.replace_if(register, |current: &T| Some(current.clone()))
+            .unwrap_or_else(|| {
+                let node = self.cursor.to_vec();
+                panic!("{}", HookError::StaleSetter { node })
+            })
+    }
+
+    /// swaps in `register` if `check` returns something for the current value, all while the
+    /// StateTree stays locked. None if the register got dropped. This applies right away even
+    /// during Runtime::batch, as `check` has to see the value left by the writes before it.
+    fn replace_if<T, R>(&self, register: Register, check: impl FnOnce(&T) -> Option<R>) -> Option<R>
+    where
+        T: 'static + MaybeSend,
+    {
+        let tree = self.runtime.lock();
+        let state = tree.try_get_state(&self.cursor).ok()?;
+
+        let mut registers = state.write();
+        let mut current = registers.current_mut(self.index, self.slot)?;
+
+        let result = check(
+            current
+                .get::<T>(self.index)
+                .unwrap_or_else(|e| panic!("{}", e)),
+        )?;
+
+        #[cfg(feature = "history")]
+        let type_name = register.type_name;
+        let previous = current.overwrite(register);
+        state.mark_dirty();
+
+        #[cfg(feature = "history")]
+        self.runtime
+            .record(self.cursor.to_vec(), self.index, type_name, previous);
+        #[cfg(not(feature = "history"))]
+        drop(previous);
+
+        drop(current);
+        drop(registers);
+        let transition = Transition::of(state, &self.cursor, self.index);
+        drop(tree);
+
+        self.runtime.transition(transition);
+        self.runtime.notify();
+        Some(result)
+    }
+}
+
+/// setter of use_state, which replaces the stored value. It can be called just like a function,
+/// but unlike a closure it can be named, e.g. to store it in a struct, and cloned.
+pub struct Setter<T> {
+    /// shared by all clones, so they don't need to copy the cursor
+    register: Shared<Handle>,
+    /// what calling the setter does, so it can deref to a function
+    set: Shared<SetFn<T>>,
+    /// stores a value in a register, which is where use_state and use_state_copy differ
+    make: fn(T) -> Register,
+}
+
+impl<T> Setter<T>
+where
+    T: 'static + MaybeSend,
+{
+    fn new(register: Handle, make: fn(T) -> Register) -> Self {
+        let register = Shared::new(register);
+
+        let set = {
+            let register = register.clone();
+            move |value: T| register.set(make(value))
+        };
+
+        Setter {
+            register,
+            set: Shared::new(set),
+            make,
+        }
+    }
+
+    /// number of writes to the register so far, through this setter and any others of the same
+    /// register. It gets bumped along with every write, while the StateTree is locked, so
+    /// concurrent writers can tell whose write came last. Writes deferred by Runtime::batch only
+    /// count once they got applied. 0 if the register got dropped, e.g. by Runtime::reset.
+    pub fn last_seq(&self) -> u64 {
+        let handle = &self.register;
+        let tree = handle.runtime.lock();
+        let Ok(state) = tree.try_get_state(&handle.cursor) else {
+            return 0;
+        };
+        if state.generation != handle.generation {
+            return 0;
+        }
+
+        let registers = state.read();
+        let register = registers
+            .generation(handle.index)
+            .filter(|&slot| slot == handle.slot)
+            .and_then(|_| registers.get(handle.index));
+        register.map_or(0, |register| register.seq)
+    }
+
+    /// replaces the stored value, same as calling the setter
+    pub fn call(&self, value: T) {
+        self.register.set((self.make)(value))
+    }
+
+    /// like calling the setter, but fails instead of writing if the state this setter belongs to
+    /// got thrown away since, e.g. by Runtime::reset or Runtime::replace_subtree, so a stale
+    /// setter can't write to a register of some other hook. During Runtime::batch, this gets
+    /// checked when try_set is called rather than when the write gets applied.
+    pub fn try_set(&self, value: T) -> Result<(), HookError> {
+        self.register.check()?;
+        self.call(value);
+        Ok(())
+    }
+
+    /// replaces the stored value and returns the previous one. The StateTree stays locked in
+    /// between, so no other write can sneak in. Unlike calling the setter this applies
+    /// immediately, even during Runtime::batch, where it doesn't see the writes the batch
+    /// deferred so far.
+    pub fn replace(&self, value: T) -> T
+    where
+        T: Clone,
+    {
+        self.register.replace((self.make)(value))
+    }
+
+    /// stores `new` only if the current value still equals `expected`, returning whether it did.
+    /// The StateTree stays locked from the comparison to the write, so retry loops can build on
+    /// it. Like replace this applies immediately, even during Runtime::batch.
+    pub fn compare_and_set(&self, expected: T, new: T) -> bool
+    where
+        T: PartialEq,
+    {
+        let register = (self.make)(new);
+        self.register
+            .replace_if(register, |current: &T| (*current == expected).then_some(()))
+            .is_some()
+    }
+
+    /// changes the stored value in place, e.g. a single field of a big struct, without cloning
+    /// it. The StateTree stays locked while `f` runs, so `f` must not use state, and like
+    /// calling the setter this gets deferred during Runtime::batch. Such changes can't be undone
+    /// through the history.
+    pub fn update_with(&self, f: impl FnOnce(&mut T) + MaybeSend + 'static) {
+        self.register.update(f)
+    }
+}
+
+impl<T> Clone for Setter<T> {
+    fn clone(&self) -> Self {
+        Setter {
+            register: self.register.clone(),
+            set: self.set.clone(),
+            make: self.make,
+        }
+    }
+}
+
+/// makes the setter callable like a function
+impl<T> Deref for Setter<T> {
+    type Target = SetFn<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.set
+    }
+}
+
+/// returned by use_counter. Every action reads the count under the same lock it writes it
+/// with, so concurrent actions don't lose updates. Like setters they get deferred during
+/// Runtime::batch.
+#[derive(Clone)]
+pub struct CounterActions {
+    handle: Shared<Handle>,
+    init: i64,
+}
+
+impl CounterActions {
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn dec(&self) {
+        self.add(-1)
+    }
+
+    pub fn add(&self, n: i64) {
+        self.handle.update(move |count: &mut i64| *count += n)
+    }
+
+    /// goes back to the count passed to use_counter on the first render
+    pub fn reset(&self) {
+        let init = self.init;
+        self.handle.update(move |count: &mut i64| *count = init)
+    }
+}
+
+pub struct Hook {
+    /// the Runtime whose StateTree this hook reads from
+    runtime: Runtime,
+    /// points to State in the runtime's StateTree
+    cursor: Cursor,
+    /// the registers and children (of the StateTree referenced by cursor) used by this render
+    /// pass so far
+    pass: RenderPass,
+    /// registers handed out by read_guard, kept alive for as long as their guards might be
+    pins: Lock<Vec<SharedRegister>>,
+}
+
+/// a cursor into the StateTree, displayed like `0/2/1`, or `/` for the root
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Path<'a>(pub &'a [usize]);
+
+impl fmt::Display for Path<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some((first, rest)) = self.0.split_first() else {
+            return write!(f, "/");
+        };
+
+        write!(f, "{first}")?;
+        for slot in rest {
+            write!(f, "/{slot}")?;
+        }
+        Ok(())
+    }
+}
+
+/// a hook into the global Runtime
+impl Default for Hook {
+    fn default() -> Self {
+        Runtime::global().hook()
+    }
+}
+
+impl Hook {
+    /// a hook pointing to the State at `cursor`, which has to exist
+    pub(crate) fn at(runtime: Runtime, cursor: &[usize]) -> Self {
+        Hook {
+            cursor: cursor.iter().copied().collect(),
+            ..Hook::new(runtime)
+        }
+    }
+
+    pub(crate) fn new(runtime: Runtime) -> Self {
+        Hook {
+            runtime,
+            cursor: Cursor::new(),
+            pass: RenderPass::default(),
+            pins: Lock::default(),
+        }
+    }
+
+    /// prepares the hook for another render pass, so the same Hook can be used for every render.
+    /// Hooks called for the first time during the pass, e.g. behind an `if`, get fresh state
+    /// rather than adopting the state of other hooks by position, which hooks of a Hook that
+    /// didn't begin a render do. The same goes for the children it renders.
+    pub fn begin_render(&mut self) {
+        self.pass = RenderPass::default();
+        self.pass.claims.by_id = true;
+        self.pins.lock().clear();
+    }
+
+    /// number of hooks this component used since begin_render
+    pub fn hooks_used(&self) -> usize {
+        self.pass.claims.count
+    }
+
+    /// where in the StateTree this component keeps its state, e.g. for logging. Wrap it in a
+    /// Path to display it.
+    pub fn path(&self) -> &[usize] {
+        &self.cursor
+    }
+
+    /// finishes a render pass. Returns an error if this component, or any child rendered during
+    /// this pass, used a different number of hooks than on its first render, which usually means
+    /// a hook got called conditionally by mistake.
+    ///
+    /// Registers of hooks that didn't get called during this pass get dropped, running their
+    /// cleanups. Hooks that are only called on some renders start out fresh that way whenever
+    /// they get called again, they need to use use_state_named or a keyed child to keep their
+    /// state in between. Either way they don't disturb the state of the other hooks.
+    pub fn end_render(&mut self) -> Result<(), HookError> {
+        let (unused, result) = {
+            let mut tree = self.runtime.lock();
+            let node = tree.get_tree_mut(&self.cursor);
+            let unused = self.pass.retain_claimed(node);
+            (unused, self.pass.finish(node, &self.cursor))
+        };
+
+        // cleanups may use state themselves, so they can only run once the tree is unlocked
+        drop(unused);
+        result.map_err(HookError::HookOrder)
+    }
+
+    /// returns the state stored by this hook, or `value` if this is the first render.
+    /// Calling the returned function replaces the stored state.
+    ///
+    /// Like every other hook, this finds its state by where it gets called from, along with how
+    /// many times it got called from there during this render pass, so hooks called in a loop
+    /// need to be called in the same order every time. A hook that only gets called on some
+    /// renders doesn't shift the state of the hooks called after it. A hook that isn't known to
+    /// the component yet, and gets called before any known one, adopts the first unclaimed
+    /// register though, so state seeded by RuntimeBuilder or a snapshot finds its hooks.
+    #[track_caller]
+    pub fn use_state<T>(&mut self, value: T) -> (T, Setter<T>)
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        self.use_state_with(|| value)
+    }
+
+    /// like use_state, but the initial value only gets computed on the very first render
+    #[track_caller]
+    pub fn use_state_with<T>(&mut self, init: impl FnOnce() -> T) -> (T, Setter<T>)
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        let runtime = self.runtime.clone();
+        let (index, value, _) = self
+            .use_register(&runtime.lock(), || Register::new(init()))
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        // the StateTree is unlocked again at this point, so the setter may be called right away
+        (value, self.setter(index, Register::new))
+    }
+
+    /// like use_state, starting out with `T::default()`, which only gets constructed on the very
+    /// first render
+    #[track_caller]
+    pub fn use_state_default<T>(&mut self) -> (T, Setter<T>)
+    where
+        T: 'static + Clone + Default + MaybeSend,
+    {
+        self.use_state_with(T::default)
+    }
+
+    /// like use_state, additionally returning whether the state just got created, rather than
+    /// recovered from a previous render, e.g. to count mounts
+    #[track_caller]
+    pub fn use_state_tracked<T>(&mut self, value: T) -> (T, Setter<T>, bool)
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        let runtime = self.runtime.clone();
+        let (index, value, created) = self
+            .use_register(&runtime.lock(), || Register::new(value))
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        (value, self.setter(index, Register::new), created)
+    }
+
+    /// like use_state, but returns an error instead of panicking if the state can't be retrieved
+    #[track_caller]
+    pub fn try_use_state<T>(&mut self, value: T) -> Result<(T, Setter<T>), HookError>
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        self.try_use_state_with(|| value)
+    }
+
+    /// like use_state_with, but returns an error instead of panicking. Unlike use_state, this
+    /// also reports if the state got poisoned.
+    #[track_caller]
+    pub fn try_use_state_with<T>(
+        &mut self,
+        init: impl FnOnce() -> T,
+    ) -> Result<(T, Setter<T>), HookError>
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        let runtime = self.runtime.clone();
+        let (index, value, _) =
+            self.use_register(&*runtime.try_lock()?, || Register::new(init()))?;
+
+        Ok((value, self.setter(index, Register::new)))
+    }
+
+    /// claims the register of the hook calling this, only initializing it with `init` if there
+    /// is none yet. Returns its index along with a clone of its value, and whether it just got
+    /// created.
+    #[track_caller]
+    fn use_register<T>(
+        &mut self,
+        tree: &StateTree,
+        init: impl FnOnce() -> Register,
+    ) -> Result<(usize, T, bool), HookError>
+    where
+        T: 'static + Clone,
+    {
+        let id = self.try_next_id()?;
+        let state = tree.try_get_state(&self.cursor)?;
+        state.try_use_register(&mut self.pass.claims, id, init)
+    }
+
+    /// the id of the hook calling this, which every hook needs to call exactly once to find its
+    /// register. Hooks calling other hooks get their id from those. Panics once the component
+    /// used more hooks than Runtime::max_hooks_per_node allows.
+    #[track_caller]
+    fn next_id(&mut self) -> HookId {
+        self.try_next_id().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// like next_id, but returns HookError::TooManyHooks instead of panicking
+    #[track_caller]
+    fn try_next_id(&mut self) -> Result<HookId, HookError> {
+        let limit = self.runtime.max_hooks_per_node();
+        if self.pass.claims.count >= limit {
+            let node = self.cursor.to_vec();
+            return Err(HookError::TooManyHooks { node, limit });
+        }
+
+        Ok(self.pass.claims.next(Location::caller()))
+    }
+
+    /// a handle to register `index` of this component, for writing to it later
+    fn handle(&self, index: usize) -> Handle {
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let slot = state.read().generation(index);
+
+        Handle {
+            runtime: self.runtime.clone(),
+            cursor: self.cursor.clone(),
+            index,
+            generation: state.generation,
+            slot: slot.expect("the register got claimed during this render"),
+        }
+    }
+
+    /// returns a setter replacing the value of state register `index`
+    fn setter<T>(&self, index: usize, make: fn(T) -> Register) -> Setter<T>
+    where
+        T: 'static + MaybeSend,
+    {
+        Setter::new(self.handle(index), make)
+    }
+
+    /// like use_state, but the state is identified by `key` instead of where the hook gets called
+    /// from. Named hooks don't count towards the hooks of a render pass, so they may be skipped on
+    /// some renders, and keep their state even across Hook::end_render. Writes through the
+    /// returned function don't get recorded by the history.
+    pub fn use_state_named<T>(&mut self, key: &str, value: T) -> (T, impl Fn(T))
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        let value = {
+            let tree = self.runtime.lock();
+            let state = tree.get_state(&self.cursor);
+            let mut named = state.named.write();
+
+            named
+                .entry(key.to_string())
+                .or_insert_with(|| Register::new(value))
+                .get::<T>(0)
+                .unwrap_or_else(|_| {
+                    panic!(
+                        "state hook {:?} to be of type {}",
+                        key,
+                        std::any::type_name::<T>()
+                    )
+                })
+                .clone()
+        };
+
+        let runtime = self.runtime.clone();
+        let cursor = self.cursor.clone();
+        let key = key.to_string();
+        let set = move |value: T| {
+            let key = key.clone();
+            runtime.write(&cursor, None, move |state| {
+                state.named.write().insert(key, Register::new(value));
+            });
+        };
+
+        (value, set)
+    }
+
+    /// like use_state_named, but the state is identified by any hashable `key`, so a hook behind
+    /// an `if` or in a loop over items keeps its state while it isn't called. Keys are told
+    /// apart by their type and hash, so two different keys of the same type with equal hashes
+    /// share their state. Panics if a hook with an equal key stored a different type, see
+    /// try_use_state_keyed.
+    pub fn use_state_keyed<K, T>(&mut self, key: K, value: T) -> (T, impl Fn(T))
+    where
+        K: 'static + Hash + Eq,
+        T: 'static + Clone + MaybeSend,
+    {
+        self.try_use_state_keyed(key, value)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// like use_state_keyed, but returns an error instead of panicking if a hook with an equal
+    /// key stored a different type, rather than letting the two share a register
+    pub fn try_use_state_keyed<K, T>(
+        &mut self,
+        key: K,
+        value: T,
+    ) -> Result<(T, impl Fn(T)), HookError>
+    where
+        K: 'static + Hash + Eq,
+        T: 'static + Clone + MaybeSend,
+    {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let key = (TypeId::of::<K>(), hasher.finish());
+
+        let value = {
+            let tree = self.runtime.try_lock()?;
+            let state = tree.try_get_state(&self.cursor)?;
+            let mut keyed = state.keyed.write();
+            let register = keyed.entry(key).or_insert_with(|| Register::new(value));
+            let found = register.type_name;
+
+            register
+                .downcast_ref::<T>()
+                .ok_or(HookError::KeyMismatch {
+                    expected: std::any::type_name::<T>(),
+                    found,
+                })?
+                .clone()
+        };
+
+        let runtime = self.runtime.clone();
+        let cursor = self.cursor.clone();
+        let set = move |value: T| {
+            runtime.write(&cursor, None, move |state| {
+                state.keyed.write().insert(key, Register::new(value));
+            });
+        };
+
+        Ok((value, set))
+    }
+
+    /// passes a reference to the value of the `index`th hook of this component to `f`, e.g. to
+    /// look at a big value without cloning it. This isn't a hook itself, so it may be called any
+    /// number of times. The StateTree stays locked while `f` runs, so `f` must not use state.
+    pub fn read_state<T: 'static, R>(&self, index: usize, f: impl FnOnce(&T) -> R) -> R {
+        self.try_read_state(index, f)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// like read_state, but returns an error instead of panicking, if the state got poisoned,
+    /// there is no `index`th hook or it doesn't hold a `T`
+    pub fn try_read_state<T: 'static, R>(
+        &self,
+        index: usize,
+        f: impl FnOnce(&T) -> R,
+    ) -> Result<R, HookError> {
+        let tree = self.runtime.try_lock()?;
+        tree.try_get_state(&self.cursor)?.read_state(index, f)
+    }
+
+    /// like read_state, but the reference gets returned in a guard, which can be held on to
+    /// instead of having to do everything in a closure. None if there is no `index`th hook or it
+    /// doesn't hold a `T`. Only that register stays locked until the guard is dropped, so other
+    /// state can be used meanwhile. Writing the register itself waits for the guard like with
+    /// any RwLock, keeping the StateTree locked while it waits: a setter of that register called
+    /// on the same thread deadlocks (or panics with the `thread-local` feature), and once
+    /// another thread is waiting to write it, using any state on this thread deadlocks too.
+    /// With the `parking_lot` feature the guard is one of its mapped guards.
+    pub fn read_guard<T>(&self, index: usize) -> Option<StateRef<'_, T>>
+    where
+        T: 'static + MaybeSync,
+    {
+        let register = {
+            let tree = self.runtime.lock();
+            let registers = tree.try_get_state(&self.cursor).ok()?.read();
+            registers.shared(index)?
+        };
+
+        // SAFETY: the register gets pinned to this hook, whose pins only get dropped through
+        // `&mut self`, so it lives longer than the returned guard
+        let cell = unsafe { &*Shared::as_ptr(&register) };
+        self.pins.lock().push(register);
+        map_register(cell.read())
+    }
+
+    /// like use_state, but small values get stored inline instead of on the heap, which saves
+    /// an allocation on every write. Every primitive fits, up to u128. Values bigger than 16
+    /// bytes get boxed anyway.
+    #[track_caller]
+    pub fn use_state_copy<T>(&mut self, value: T) -> (T, Setter<T>)
+    where
+        T: 'static + Copy + MaybeSend,
+    {
+        let runtime = self.runtime.clone();
+        let (index, value, _) = self
+            .use_register(&runtime.lock(), || Register::copy(value))
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        (value, self.setter(index, Register::copy))
+    }
+
+    /// like use_state, but the value can only be changed by dispatching actions, which get
+    /// applied to the current value by `reducer`
+    #[track_caller]
+    pub fn use_reducer<S, A>(&mut self, init: S, reducer: fn(S, A) -> S) -> (S, impl Fn(A))
+    where
+        S: 'static + Clone + MaybeSend,
+        A: 'static + MaybeSend,
+    {
+        let id = self.next_id();
+        let (index, value) =
+            self.runtime
+                .lock()
+                .get_state(&self.cursor)
+                .use_state(&mut self.pass.claims, id, init);
+
+        let handle = self.handle(index);
+        let dispatch = move |action: A| {
+            handle.update(move |state: &mut S| *state = reducer(state.clone(), action));
+        };
+
+        (value, dispatch)
+    }
+
+    /// applies `select` to the value of register `index`, e.g. the state of a use_reducer, and
+    /// returns the result along with whether it changed since the previous render. Components
+    /// only interested in a slice of a bigger state can skip work when other parts of it changed.
+    /// `select` only runs again once the register got written to, and runs while the StateTree
+    /// is locked, so it must not use state. Panics if there is no register `index` holding an
+    /// `S`.
+    #[track_caller]
+    pub fn use_selector<S, R>(&mut self, index: usize, select: impl Fn(&S) -> R) -> (R, bool)
+    where
+        S: 'static,
+        R: 'static + Clone + PartialEq + MaybeSend,
+    {
+        let id = self.next_id();
+
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let mut registers = state.write();
+
+        let (slot, seq) = match (registers.generation(index), registers.get(index)) {
+            (Some(slot), Some(source)) => (slot, source.seq),
+            _ => panic!("selector to read from hook #{index}, which doesn't exist"),
+        };
+        let select = |registers: &Registers| {
+            let source = registers
+                .get(index)
+                .expect("the register got checked above");
+            select(source.get::<S>(index).unwrap_or_else(|e| panic!("{}", e)))
+        };
+
+        let Some(own) = registers.find(&mut self.pass.claims, id) else {
+            let value = select(&registers);
+            let selection = Selection { slot, seq, value };
+            registers.insert(&mut self.pass.claims, id, Register::new(selection.clone()));
+            return (selection.value, true);
+        };
+
+        let register = registers
+            .get(own)
+            .unwrap_or_else(|| panic!("selector hook #{own} to exist"));
+        let previous = register
+            .get::<Selection<R>>(own)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        if previous.slot == slot && previous.seq == seq {
+            return (previous.value.clone(), false);
+        }
+        drop(register);
+
+        let value = select(&registers);
+        let mut register = registers
+            .get_mut(own)
+            .expect("the register got checked above");
+        let previous = register
+            .get_mut::<Selection<R>>(own)
+            .expect("the register got checked above");
+
+        let changed = previous.value != value;
+        *previous = Selection {
+            slot,
+            seq,
+            value: value.clone(),
+        };
+
+        (value, changed)
+    }
+
+    /// like use_reducer, but actions get sent to a background thread, which applies them in the
+    /// order they got dispatched. Takes up two registers, one for the state and one for the
+    /// Dispatcher.
+    #[cfg(not(feature = "thread-local"))]
+    #[track_caller]
+    pub fn use_reducer_async<S, A>(&mut self, init: S, reducer: fn(S, A) -> S) -> (S, Dispatcher<A>)
+    where
+        S: 'static + Clone + Send,
+        A: 'static + Send,
+    {
+        let (value_id, dispatcher_id) = (self.next_id(), self.next_id());
+
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let (index, value) = state.use_state(&mut self.pass.claims, value_id, init);
+        let generation = state
+            .read()
+            .generation(index)
+            .expect("the register just got claimed");
+
+        let (runtime, cursor) = (self.runtime.downgrade(), self.cursor.clone());
+        let (_, dispatcher) = state.use_state_with(&mut self.pass.claims, dispatcher_id, || {
+            Dispatcher::spawn(runtime, cursor, (index, generation), reducer)
+        });
+
+        (value, dispatcher)
+    }
+
+    /// like use_memo, but the value is shared instead of cloned on every render, so it doesn't
+    /// need to implement Clone. Renders with unchanged deps return the same Arc.
+    #[track_caller]
+    pub fn use_memo_arc<T, D>(&mut self, deps: D, compute: impl FnOnce() -> T) -> Arc<T>
+    where
+        T: 'static + MaybeSend + MaybeSync,
+        D: 'static + Clone + MaybeSend + PartialEq,
+    {
+        self.use_memo(deps, || Arc::new(compute()))
+    }
+
+    /// returns the value computed by `compute`, which only gets called again once `deps` differ
+    /// from those of the previous render
+    #[track_caller]
+    pub fn use_memo<T, D>(&mut self, deps: D, compute: impl FnOnce() -> T) -> T
+    where
+        T: 'static + Clone + MaybeSend,
+        D: 'static + Clone + MaybeSend + PartialEq,
+    {
+        let id = self.next_id();
+
+        // look for a cached value first, but don't hold on to the lock while computing a new one
+        let (index, cached) = {
+            let tree = self.runtime.lock();
+            let state = tree.get_state(&self.cursor);
+            let mut registers = state.write();
+            let index = registers.find(&mut self.pass.claims, id);
 
-        let mut hook = super::Hook::default();
+            let cached = index.and_then(|index| {
+                let register = registers.get(index)?;
+                let (old_deps, value) = register.downcast_ref::<(D, T)>().unwrap_or_else(|| {
+                    panic!(
+                        "memo hook #{} to be of type {}",
+                        index,
+                        std::any::type_name::<(D, T)>()
+                    )
+                });
+
+                (*old_deps == deps).then(|| value.clone())
+            });
+            (index, cached)
+        };
+
+        if let Some(value) = cached {
+            return value;
+        }
+
+        let value = compute();
 
-        let (a, _) = hook.use_state("no");
-        let (b, _) = hook.use_state(1231);
-        let (c, _) = hook.use_state(3.14325);
-        let (d, _) = hook.use_state(false);
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let mut registers = state.write();
+        let memo = Register::new((deps, value.clone()));
+        match index.filter(|&index| registers.contains(index)) {
+            Some(index) => {
+                *registers
+                    .get_mut(index)
+                    .expect("the register got checked above") = memo;
+            }
+            None => {
+                registers.insert(&mut self.pass.claims, id, memo);
+            }
+        }
 
-        assert_eq!(a, "what");
-        assert_eq!(b, 123);
-        assert_eq!(c, 3.145);
-        assert_eq!(d, true);
+        value
     }
 
-    #[test]
-    fn set_state() {
-        let mut hook = super::Hook::default();
-        let (_, set_a) = hook.use_state("what");
-        let (_, set_b) = hook.use_state(123);
-        let (_, set_c) = hook.use_state(3.145);
-        let (_, set_d) = hook.use_state(true);
-        set_a("möp");
-        set_b(314);
-        set_c(0.0);
-        set_d(false);
+    /// like use_memo, but only a hash of `deps` gets stored and compared, which is cheaper for
+    /// big deps and doesn't need them to be Clone or 'static. Deps may also be passed by
+    /// reference. Different deps with equal hashes are mistaken for unchanged ones, so `compute`
+    /// might not get called again in the rare case of a collision.
+    #[track_caller]
+    pub fn use_memo_hashed<T, D>(&mut self, deps: D, compute: impl FnOnce() -> T) -> T
+    where
+        T: 'static + Clone + MaybeSend,
+        D: Hash,
+    {
+        let mut hasher = DefaultHasher::new();
+        deps.hash(&mut hasher);
+        self.use_memo(hasher.finish(), compute)
+    }
 
-        let mut hook = super::Hook::default();
+    /// like use_memo, but every value that gets replaced by a new one is passed to `cleanup`
+    /// first, e.g. to close a file it holds. So is the last value, once the component's state is
+    /// dropped.
+    #[track_caller]
+    pub fn use_memo_cleanup<T, D>(
+        &mut self,
+        deps: D,
+        compute: impl FnOnce() -> T,
+        cleanup: impl Fn(&T) + Send + 'static,
+    ) -> T
+    where
+        T: 'static + Clone + MaybeSend,
+        D: 'static + Clone + MaybeSend + PartialEq,
+    {
+        let id = self.next_id();
+
+        let (index, cached) = {
+            let tree = self.runtime.lock();
+            let state = tree.get_state(&self.cursor);
+            let mut registers = state.write();
+            let index = registers.find(&mut self.pass.claims, id);
+
+            let cached = index.and_then(|index| {
+                let register = registers.get(index)?;
+                let memo = register
+                    .get::<MemoCleanup<D, T>>(index)
+                    .unwrap_or_else(|e| panic!("{}", e));
+
+                (memo.deps == deps).then(|| memo.value.clone())
+            });
+            (index, cached)
+        };
+
+        if let Some(value) = cached {
+            return value;
+        }
+
+        let value = compute();
+        let memo = Register::new(MemoCleanup {
+            deps,
+            value: value.clone(),
+            cleanup: Box::new(cleanup),
+        });
 
-        let (a, _) = hook.use_state("what");
-        let (b, _) = hook.use_state(123);
-        let (c, _) = hook.use_state(3.145);
-        let (d, _) = hook.use_state(true);
+        let replaced = {
+            let tree = self.runtime.lock();
+            let state = tree.get_state(&self.cursor);
+            let mut registers = state.write();
+            match index.filter(|&index| registers.contains(index)) {
+                Some(index) => {
+                    let mut register = registers
+                        .get_mut(index)
+                        .expect("the register got checked above");
+                    Some(std::mem::replace(&mut *register, memo))
+                }
+                None => {
+                    registers.insert(&mut self.pass.claims, id, memo);
+                    None
+                }
+            }
+        };
 
-        assert_eq!(a, "möp");
-        assert_eq!(b, 314);
-        assert_eq!(c, 0.0);
-        assert_eq!(d, false);
+        // the cleanup may use state itself, so it can only run once the tree is unlocked
+        drop(replaced);
+        value
     }
-}
 
-lazy_static! {
-    static ref STATE_TREE: Mutex<StateTree> = Mutex::new(StateTree::default());
-}
+    /// runs `effect` on the first render and whenever `deps` changed since the previous render.
+    /// The cleanup returned by the effect gets run before the next effect, or when the
+    /// component's state is dropped.
+    #[track_caller]
+    pub fn use_effect<D>(&mut self, deps: D, effect: impl FnOnce() -> Box<dyn FnOnce() + Send>)
+    where
+        D: 'static + Clone + MaybeSend + PartialEq,
+    {
+        let id = self.next_id();
 
-#[derive(Default)]
-struct StateTree {
-    state: State,
+        // take the previous cleanup out of the register, unless deps didn't change
+        let (index, cleanup) = {
+            let tree = self.runtime.lock();
+            let state = tree.get_state(&self.cursor);
+            let mut registers = state.write();
+            let index = registers.find(&mut self.pass.claims, id);
 
-    /// When some component uses state, it's subcomponents will get their own state, which can be
-    /// found here
-    children: Vec<StateTree>,
+            match index.filter(|&index| registers.contains(index)) {
+                None => (None, None),
+                Some(index) => {
+                    let mut register = registers
+                        .get_mut(index)
+                        .expect("the register got checked above");
+                    let old = register.downcast_mut::<Effect<D>>().unwrap_or_else(|| {
+                        panic!(
+                            "effect hook #{} to have dependencies of type {}",
+                            index,
+                            std::any::type_name::<D>()
+                        )
+                    });
 
-    /// pointer to the currently selected sub state.
-    cursor: usize,
-}
+                    if old.deps == deps {
+                        return;
+                    }
+
+                    (Some(index), old.cleanup.take())
+                }
+            }
+        };
 
-impl StateTree {
-    fn get_state(&self, cursor: &[usize]) -> &State {
-        if cursor.is_empty() {
-            return &self.state;
+        // neither cleanup nor effect may run while the StateTree is locked, they might use state
+        if let Some(cleanup) = cleanup {
+            cleanup();
         }
+        let cleanup = effect();
 
-        self.children[cursor[0]].get_state(&cursor[1..])
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let mut registers = state.write();
+        let effect = Register::new(Effect {
+            deps,
+            cleanup: Some(cleanup),
+        });
+        match index.filter(|&index| registers.contains(index)) {
+            Some(index) => {
+                *registers
+                    .get_mut(index)
+                    .expect("the register got checked above") = effect;
+            }
+            None => {
+                registers.insert(&mut self.pass.claims, id, effect);
+            }
+        }
     }
-}
 
-type AnyBox = Box<dyn Any + Send>;
-#[derive(Default)]
-struct State {
-    /// each state holds multible state registers that can be retrieved one after another
-    registers: RwLock<Vec<AnyBox>>,
-}
+    /// returns `f`, or the closure passed on a previous render if `deps` didn't change since.
+    /// The returned Arc stays the same as long as the deps do, so children receiving it can
+    /// compare it with Arc::ptr_eq.
+    #[track_caller]
+    pub fn use_callback<F, D>(&mut self, deps: D, f: F) -> Arc<F>
+    where
+        F: 'static + MaybeSend + MaybeSync,
+        D: 'static + Clone + MaybeSend + PartialEq,
+    {
+        self.use_memo(deps, || Arc::new(f))
+    }
+
+    /// returns the `value` passed on the previous render, or None on the first one
+    #[track_caller]
+    pub fn use_previous<T>(&mut self, value: T) -> Option<T>
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        let id = self.next_id();
+
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let mut registers = state.write();
+
+        let Some(index) = registers.find(&mut self.pass.claims, id) else {
+            registers.insert(&mut self.pass.claims, id, Register::new(value));
+            return None;
+        };
+
+        let mut register = registers
+            .get_mut(index)
+            .expect("the register just got found");
+        let previous = register.downcast_mut::<T>().unwrap_or_else(|| {
+            panic!(
+                "previous hook #{} to be of type {}",
+                index,
+                std::any::type_name::<T>()
+            )
+        });
+
+        Some(std::mem::replace(previous, value))
+    }
+
+    /// like use_state for a bool, but instead of a setter this returns a function flipping the
+    /// stored value. Flips read the current value under the same lock they write it with.
+    #[track_caller]
+    pub fn use_toggle(&mut self, init: bool) -> (bool, impl Fn()) {
+        let (value, setter) = self.use_state(init);
+        let handle = setter.register;
+
+        (value, move || {
+            handle.update(|value: &mut bool| *value = !*value)
+        })
+    }
+
+    /// like use_state for a number, but instead of a setter this returns actions updating the
+    /// stored count
+    #[track_caller]
+    pub fn use_counter(&mut self, init: i64) -> (i64, CounterActions) {
+        let (value, setter) = self.use_state(init);
+
+        let actions = CounterActions {
+            handle: setter.register,
+            init,
+        };
+
+        (value, actions)
+    }
 
-impl State {
-    fn use_state<T>(&self, value: T, index: usize) -> T
+    /// returns `value` once it stopped changing for `delay`, and the last value that did so
+    /// until then. Changes within `delay` of each other start the wait over, so none of them get
+    /// returned. Subscribers of the runtime get notified once `delay` passed, so they can render
+    /// again.
+    #[track_caller]
+    pub fn use_debounce<T>(&mut self, value: T, delay: Duration) -> T
     where
-        T: 'static + Clone + Send,
+        T: 'static + Clone + MaybeSend + PartialEq,
     {
-        let head = {
-            let v = self.registers.read().expect("to read length of state");
-            v.len()
+        let id = self.next_id();
+
+        let now = self.runtime.clock().now();
+
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let mut registers = state.write();
+
+        let Some(index) = registers.find(&mut self.pass.claims, id) else {
+            let debounce = Register::new(Debounce {
+                committed: value.clone(),
+                pending: value.clone(),
+                since: now,
+            });
+            registers.insert(&mut self.pass.claims, id, debounce);
+            return value;
         };
-        assert!(index <= head);
 
-        // if this State got called the first time from a Hook, we want to insert the value into a
-        // new state register
-        if head == index {
-            let mut state = self.registers.write().expect("to write value to state");
-            state.push(Box::new(value) as Box<dyn Any + Send>);
+        let mut register = registers
+            .get_mut(index)
+            .expect("the register just got found");
+        let debounce = register
+            .get_mut::<Debounce<T>>(index)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let changed = debounce.pending != value;
+        if changed {
+            debounce.pending = value;
+            debounce.since = now;
         }
 
-        // retrieve value from state
-        let state = self.registers.read().expect("to read value from state");
-        state[index]
-            .downcast_ref::<T>()
-            .unwrap_or_else(|| {
-                panic!(
-                    "state hook #{} to be of type {}",
-                    index,
-                    std::any::type_name::<T>()
-                )
-            })
-            .clone()
+        if debounce.committed != debounce.pending && now - debounce.since >= delay {
+            debounce.committed = debounce.pending.clone();
+        }
+
+        let committed = debounce.committed.clone();
+        drop(register);
+        drop(registers);
+        drop(tree);
+
+        if changed {
+            let runtime = self.runtime.clone();
+            let wake = move || {
+                runtime.lock().state.mark_dirty();
+                runtime.notify();
+            };
+
+            self.runtime.clock().schedule(delay, Box::new(wake));
+        }
+
+        committed
     }
-}
 
-#[derive(Default)]
-struct Hook {
-    // TODO Reference counted [usize] might be a better fit in order to avoid cloning in set_value
-    // closure
-    /// points to State in global StateTree
-    cursor: Vec<usize>,
-    /// points to the next state register (of state referenced by cursor) to be retrieved
-    counter: usize,
-}
+    /// returns `value`, but changes at most once every `interval`. A change within `interval` of
+    /// the previous one gets held back until the window opens again, and subscribers of the
+    /// runtime get notified then, so the latest value isn't lost when the changes stop.
+    #[track_caller]
+    pub fn use_throttle<T>(&mut self, value: T, interval: Duration) -> T
+    where
+        T: 'static + Clone + MaybeSend + PartialEq,
+    {
+        let id = self.next_id();
 
-impl Hook {
-    fn use_state<T>(&mut self, value: T) -> (T, impl Fn(T))
+        let now = self.runtime.clock().now();
+
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let mut registers = state.write();
+
+        let Some(index) = registers.find(&mut self.pass.claims, id) else {
+            let throttle = Register::new(Throttle {
+                emitted: value.clone(),
+                pending: value.clone(),
+                since: now,
+                scheduled: false,
+            });
+            registers.insert(&mut self.pass.claims, id, throttle);
+            return value;
+        };
+
+        let mut register = registers
+            .get_mut(index)
+            .expect("the register just got found");
+        let throttle = register
+            .get_mut::<Throttle<T>>(index)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        throttle.pending = value;
+        let elapsed = now - throttle.since;
+
+        let mut schedule = None;
+        if throttle.pending != throttle.emitted {
+            if elapsed >= interval {
+                throttle.emitted = throttle.pending.clone();
+                throttle.since = now;
+                throttle.scheduled = false;
+            } else if !throttle.scheduled {
+                throttle.scheduled = true;
+                schedule = Some(interval - elapsed);
+            }
+        }
+
+        let emitted = throttle.emitted.clone();
+        drop(register);
+        drop(registers);
+        drop(tree);
+
+        if let Some(delay) = schedule {
+            let runtime = self.runtime.clone();
+            let wake = move || {
+                runtime.lock().state.mark_dirty();
+                runtime.notify();
+            };
+
+            self.runtime.clock().schedule(delay, Box::new(wake));
+        }
+
+        emitted
+    }
+
+    /// returns the value of the previous render while `value` keeps changing, catching up with it
+    /// on a render after the runtime's clock got to run the update, e.g. so an expensive list
+    /// doesn't get rebuilt for every key stroke. Subscribers of the runtime get notified once the
+    /// update is due, so the latest value shows up eventually.
+    ///
+    /// With the `thread-local` feature and the SystemClock, the update only runs once the thread
+    /// calls SystemClock::run_due.
+    #[track_caller]
+    pub fn use_deferred_value<T>(&mut self, value: T) -> T
     where
-        T: 'static + Clone + Send,
+        T: 'static + Clone + MaybeSend + PartialEq,
     {
-        // index is the currently active state register
-        let index = self.counter;
+        let id = self.next_id();
+
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let mut registers = state.write();
+
+        let Some(index) = registers.find(&mut self.pass.claims, id) else {
+            let deferred = Register::new(Deferred {
+                shown: value.clone(),
+                scheduled: false,
+                due: Shared::default(),
+            });
+            registers.insert(&mut self.pass.claims, id, deferred);
+            return value;
+        };
+
+        let mut register = registers
+            .get_mut(index)
+            .expect("the register just got found");
+        let deferred = register
+            .get_mut::<Deferred<T>>(index)
+            .unwrap_or_else(|e| panic!("{}", e));
 
-        // increment the counter so the next call to use_state will point to the following state register
-        self.counter += 1;
+        if deferred.due.swap(false, Ordering::SeqCst) {
+            deferred.scheduled = false;
+            deferred.shown = value;
+            return deferred.shown.clone();
+        }
+
+        let schedule = deferred.shown != value && !deferred.scheduled;
+        deferred.scheduled |= schedule;
+        let shown = deferred.shown.clone();
+        let due = deferred.due.clone();
+        drop(register);
+        drop(registers);
+        drop(tree);
+
+        if schedule {
+            let runtime = self.runtime.clone();
+            let wake = move || {
+                due.store(true, Ordering::SeqCst);
+                runtime.lock().state.mark_dirty();
+                runtime.notify();
+            };
+
+            self.runtime
+                .clock()
+                .schedule(Duration::ZERO, Box::new(wake));
+        }
+
+        shown
+    }
+
+    /// calls `f` every `period`, until the component gets unmounted. The interval starts over
+    /// with the `f` of the current render whenever `period` changed, otherwise `f` stays the
+    /// one of the render that started it.
+    ///
+    /// With the `thread-local` feature and the SystemClock, ticks only happen when the thread
+    /// calls SystemClock::run_due.
+    #[track_caller]
+    pub fn use_interval(&mut self, period: Duration, f: impl 'static + Fn() + MaybeSend) {
+        let id = self.next_id();
 
-        // retrieve state pointed to by hook
-        let tree: MutexGuard<'_, StateTree> = STATE_TREE.lock().expect("to read global StateTree");
+        let tree = self.runtime.lock();
         let state = tree.get_state(&self.cursor);
+        let mut registers = state.write();
+        let index = registers.find(&mut self.pass.claims, id);
+
+        if let Some((index, register)) =
+            index.and_then(|index| Some((index, registers.get(index)?)))
+        {
+            let interval = register
+                .get::<Interval>(index)
+                .unwrap_or_else(|e| panic!("{}", e));
+
+            if interval.period == period {
+                return;
+            }
+        }
+
+        let cancelled = Shared::new(AtomicBool::new(false));
+        self.runtime.track(&cancelled);
+        let interval = Register::new(Interval {
+            period,
+            cancelled: cancelled.clone(),
+        });
+
+        // replacing the old interval cancels it
+        match index.filter(|&index| registers.contains(index)) {
+            Some(index) => {
+                *registers
+                    .get_mut(index)
+                    .expect("the register got checked above") = interval;
+            }
+            None => {
+                registers.insert(&mut self.pass.claims, id, interval);
+            }
+        }
+
+        drop(registers);
+        drop(tree);
+
+        tick(self.runtime.clock().clone(), period, cancelled, f);
+    }
+
+    /// spawns the future returned by `f` on the executor of the runtime on the first render, and
+    /// whenever `deps` changed since the previous render. Returns Loading until the latest future
+    /// completed, subscribers get notified once it did. Results of futures spawned for older
+    /// deps get thrown away.
+    #[track_caller]
+    pub fn use_async<D, T, E, F>(&mut self, deps: D, f: impl FnOnce() -> F) -> AsyncState<T, E>
+    where
+        D: 'static + Clone + MaybeSend + PartialEq,
+        T: 'static + Clone + MaybeSend,
+        E: 'static + Clone + MaybeSend,
+        F: 'static + Future<Output = Result<T, E>> + MaybeSend,
+    {
+        let hook = self.next_id();
+
+        let (index, id) = {
+            let tree = self.runtime.lock();
+            let state = tree.get_state(&self.cursor);
+            let mut registers = state.write();
+
+            let id = TASKS.fetch_add(1, Ordering::Relaxed);
+            let found = registers.find(&mut self.pass.claims, hook);
+            match found.filter(|&index| registers.contains(index)) {
+                None => {
+                    let task = Register::new(AsyncTask {
+                        deps,
+                        state: AsyncState::<T, E>::Loading,
+                        id,
+                    });
+                    (registers.insert(&mut self.pass.claims, hook, task), id)
+                }
+                Some(index) => {
+                    let mut register = registers
+                        .get_mut(index)
+                        .expect("the register got checked above");
+                    let task = register
+                        .get_mut::<AsyncTask<D, T, E>>(index)
+                        .unwrap_or_else(|e| panic!("{}", e));
+
+                    if task.deps == deps {
+                        return task.state.clone();
+                    }
+
+                    *task = AsyncTask {
+                        deps,
+                        state: AsyncState::Loading,
+                        id,
+                    };
+                    (index, id)
+                }
+            }
+        };
+
+        let future = f();
+        let runtime = self.runtime.clone();
+        let cursor = self.cursor.clone();
+        let task = async move {
+            let result = future.await;
+            complete::<D, T, E>(&runtime, &cursor, index, id, result);
+        };
+
+        // neither f nor the executor may run while the StateTree is locked, they might use state
+        self.runtime.spawn(Box::pin(task));
+
+        // the executor may have run the future to completion already
+        let tree = self.runtime.lock();
+        let registers = tree.get_state(&self.cursor).read();
+        let register = registers
+            .get(index)
+            .expect("the register got claimed during this render");
+        let task = register
+            .get::<AsyncTask<D, T, E>>(index)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        task.state.clone()
+    }
 
-        // retrieve value from state, replacing value this function was called with.
-        let value = state.use_state(value, index);
+    /// mirrors some state living outside of the runtime into this component. `subscribe` gets
+    /// called on the first render, with a callback to call whenever the external state changed,
+    /// which notifies the subscribers of the runtime if the snapshot differs from the stored one.
+    /// What `subscribe` returns gets called once the component's state is dropped. Every render
+    /// takes a single snapshot and returns it, so the value stays the same throughout a render.
+    #[track_caller]
+    pub fn use_sync_external_store<T>(
+        &mut self,
+        subscribe: impl FnOnce(StoreChanged) -> Unsubscribe,
+        get_snapshot: impl Fn() -> T + MaybeSend + MaybeSync + 'static,
+    ) -> T
+    where
+        T: 'static + Clone + PartialEq + MaybeSend,
+    {
+        let id = self.next_id();
+
+        let subscribed = {
+            let tree = self.runtime.lock();
+            let mut registers = tree.get_state(&self.cursor).write();
+            registers.find(&mut self.pass.claims, id)
+        };
 
+        let snapshot = get_snapshot();
+        if let Some(index) = subscribed {
+            store_snapshot(&self.runtime.lock(), &self.cursor, index, &snapshot);
+            return snapshot;
+        }
+
+        let index = self.runtime.lock().get_state(&self.cursor).write().insert(
+            &mut self.pass.claims,
+            id,
+            Register::new(ExternalStore {
+                snapshot: snapshot.clone(),
+                unsubscribe: None,
+            }),
+        );
+
+        let runtime = self.runtime.clone();
         let cursor = self.cursor.clone();
+        let changed = move || {
+            let snapshot = get_snapshot();
+            if store_snapshot(&runtime.lock(), &cursor, index, &snapshot) {
+                runtime.notify();
+            }
+        };
+
+        // subscribe may call back right away, so the StateTree must not be locked
+        let unsubscribe = subscribe(Box::new(changed));
+
+        let tree = self.runtime.lock();
+        let mut registers = tree.get_state(&self.cursor).write();
+        let mut register = registers
+            .get_mut(index)
+            .expect("the register got claimed during this render");
+        let store = register
+            .get_mut::<ExternalStore<T>>(index)
+            .unwrap_or_else(|e| panic!("{}", e));
+        store.unsubscribe = Some(unsubscribe);
+
+        snapshot
+    }
+
+    /// returns an id like `act-3`, which is unique to this hook call and stays the same on
+    /// every render, e.g. to link labels to inputs
+    #[track_caller]
+    pub fn use_id(&mut self) -> String {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        self.use_state_with(|| format!("act-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed)))
+            .0
+    }
+
+    /// whether this is the first render of this component. Like a hook, this uses up a register,
+    /// so it has to be called on every render.
+    #[track_caller]
+    pub fn is_first_render(&mut self) -> bool {
+        let id = self.next_id();
+
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+        let mut registers = state.write();
+
+        let (index, _) = registers.claim(&mut self.pass.claims, id, || Register::copy(true));
+        let mut register = registers
+            .get_mut(index)
+            .expect("the register just got claimed");
+        let first = register
+            .get_mut::<bool>(index)
+            .unwrap_or_else(|e| panic!("{}", e));
+        std::mem::replace(first, false)
+    }
+
+    /// returns a handle to a value that can be mutated directly. The handle is the same on every
+    /// render.
+    #[track_caller]
+    pub fn use_ref<T>(&mut self, init: T) -> Arc<Mutex<T>>
+    where
+        T: 'static + MaybeSend,
+    {
+        let id = self.next_id();
+
+        let tree = self.runtime.lock();
+        let state = tree.get_state(&self.cursor);
+
+        state
+            .use_state(&mut self.pass.claims, id, Arc::new(Mutex::new(init)))
+            .1
+    }
+
+    /// returns a handle holding the `value` of the latest render, e.g. for futures spawned by an
+    /// earlier render to read the current value instead of the one they captured. The handle is
+    /// the same on every render, its content gets replaced by `value` on each.
+    #[track_caller]
+    pub fn use_latest_ref<T>(&mut self, value: T) -> Arc<RwLock<T>>
+    where
+        T: 'static + MaybeSend + MaybeSync,
+    {
+        let id = self.next_id();
+
+        let mut value = Some(value);
+        let (_, latest) = {
+            let tree = self.runtime.lock();
+            let state = tree.get_state(&self.cursor);
+            state.use_state_with(&mut self.pass.claims, id, || {
+                Arc::new(RwLock::new(value.take().unwrap()))
+            })
+        };
+
+        // not the first render, so the handle still holds the value of the previous one
+        if let Some(value) = value {
+            *latest.write().unwrap_or_else(PoisonError::into_inner) = value;
+        }
+
+        latest
+    }
+
+    /// like use_state, but the returned Updater derives the new value from the current one
+    #[track_caller]
+    pub fn use_state_fn<T>(&mut self, value: T) -> (T, Updater<T>)
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        let id = self.next_id();
+        let (index, value) =
+            self.runtime
+                .lock()
+                .get_state(&self.cursor)
+                .use_state(&mut self.pass.claims, id, value);
+
+        let updater = Updater {
+            handle: self.handle(index),
+            _value: PhantomData,
+        };
+
+        (value, updater)
+    }
+
+    /// returns a function that makes the runtime render again without changing any state, e.g.
+    /// after mutating a value of use_ref
+    #[track_caller]
+    pub fn use_force_update(&mut self) -> impl Fn() {
+        let (_, generation) = self.use_state_fn(0usize);
+        move || generation.update(|generation| generation.wrapping_add(1))
+    }
+
+    /// like use_state, but the setter leaves the register alone if the new value equals the
+    /// stored one. The setter returns whether the value changed, so like Setter::replace it
+    /// compares and writes right away, even during Runtime::batch. Two calls in the same batch
+    /// thus see each other's writes, but not the ones other setters deferred.
+    #[track_caller]
+    pub fn use_state_eq<T>(&mut self, value: T) -> (T, impl Fn(T) -> bool)
+    where
+        T: 'static + Clone + MaybeSend + PartialEq,
+    {
+        let id = self.next_id();
+        let (index, value) =
+            self.runtime
+                .lock()
+                .get_state(&self.cursor)
+                .use_state(&mut self.pass.claims, id, value);
 
+        let handle = self.handle(index);
         let set_value = move |value: T| {
-            let tree = STATE_TREE.lock().expect("to read global StateTree");
-            let state = tree.get_state(&cursor);
+            let register = Register::new(value.clone());
+            handle
+                .replace_if(register, |current: &T| (*current != value).then_some(()))
+                .is_some()
+        };
+
+        (value, set_value)
+    }
+
+    /// renders a child component with `f`. The child gets its own state, so its hooks don't
+    /// interfere with the ones of this component. Children are matched to their state by the order
+    /// they get rendered in.
+    pub fn with_child<R>(&mut self, f: impl FnOnce(&mut Hook) -> R) -> R {
+        let slot = self.next_child();
+        self.descend(slot, f)
+    }
+
+    /// like with_child, but instead of taking a closure this returns a guard that derefs to the
+    /// hook of the child, and gets back to the parent once dropped
+    pub fn enter_child(&mut self) -> ChildScope<'_> {
+        let slot = self.next_child();
+        let mut cursor = self.cursor.clone();
+        cursor.push(slot);
+
+        let mut child = Hook {
+            cursor,
+            ..Hook::new(self.runtime.clone())
+        };
+        child.pass.claims.by_id = self.pass.claims.by_id;
+
+        ChildScope {
+            parent: self,
+            child,
+        }
+    }
+
+    /// slot of the child rendered next by position, which gets created on its first render
+    fn next_child(&mut self) -> usize {
+        let mut tree = self.runtime.lock();
+        self.pass.next_child(tree.get_tree_mut(&self.cursor))
+    }
+
+    /// like with_child, but the child is matched to its state by `key` instead of the order it
+    /// got rendered in. Use this for lists, so state follows the items when they get
+    /// reordered.
+    pub fn with_keyed_child<R>(&mut self, key: &str, f: impl FnOnce(&mut Hook) -> R) -> R {
+        let slot = {
+            let mut tree = self.runtime.lock();
+            let node = tree.get_tree_mut(&self.cursor);
 
-            let mut registers = state
-                .registers
-                .write()
-                .expect("to write updated value to state");
+            let slot = match node.keys.get(key) {
+                Some(&slot) => slot,
+                None => {
+                    let slot = node.mount();
+                    node.keys.insert(key.to_string(), slot);
+                    slot
+                }
+            };
 
-            registers[index] = Box::new(value);
+            node.visit(slot);
+            slot
+        };
+
+        self.descend(slot, f)
+    }
+
+    /// drops the state of the `index`th child rendered by position, including the state of all of
+    /// its descendants, and runs their effect cleanups. Rendering the child again starts out fresh.
+    /// Its slot gets reused by the next child that gets mounted.
+    pub fn unmount_child(&mut self, index: usize) {
+        let subtree = {
+            let mut tree = self.runtime.lock();
+            let node = tree.get_tree_mut(&self.cursor);
+
+            match node.positional.get_mut(index).and_then(Option::take) {
+                Some(slot) => node.unmount(slot),
+                None => return,
+            }
+        };
+
+        // cleanups may use state themselves, so they can only run once the tree is unlocked
+        drop(subtree);
+    }
+
+    /// drops the state of every child that didn't get rendered since the last call, running their
+    /// effect cleanups. Call this at the end of a render pass, so children that disappeared start
+    /// out fresh once they get rendered again.
+    pub fn prune_unvisited(&mut self) {
+        let pruned = {
+            let mut tree = self.runtime.lock();
+            let node = tree.get_tree_mut(&self.cursor);
+            let visited = std::mem::take(&mut node.visited);
+            let visited = |slot: usize| visited.get(slot).copied().unwrap_or(false);
+
+            node.keys.retain(|_, slot| visited(*slot));
+            for slot in &mut node.positional {
+                if !slot.is_some_and(visited) {
+                    *slot = None;
+                }
+            }
+
+            let mut pruned = Vec::new();
+            for slot in 0..node.children.len() {
+                if !visited(slot) && !node.free.contains(&slot) {
+                    pruned.push(node.unmount(slot));
+                }
+            }
+
+            pruned
+        };
+
+        // cleanups may use state themselves, so they can only run once the tree is unlocked
+        drop(pruned);
+    }
+
+    /// renders `f` as a child like with_child, but a panic in it gets caught and returned as an
+    /// error instead of unwinding through the parent, so its siblings can still be rendered. The
+    /// state of a child that panicked gets thrown away, as it might be half updated, so it starts
+    /// out fresh on the next render.
+    pub fn use_error_boundary(&mut self, f: impl FnOnce(&mut Hook)) -> Result<(), BoundaryError> {
+        let index = self.pass.child;
+        let panicked = match std::panic::catch_unwind(AssertUnwindSafe(|| self.with_child(f))) {
+            Ok(()) => return Ok(()),
+            Err(payload) => payload,
+        };
+
+        // the guards got dropped while unwinding, but a lock held by the child stays poisoned
+        self.runtime.clear_poison();
+
+        let (slot, subtree) = {
+            let mut tree = self.runtime.lock();
+            let node = tree.get_tree_mut(&self.cursor);
+            let slot = node.positional[index].expect("the child just got rendered");
+            (slot, std::mem::take(&mut node.children[slot]))
+        };
+        drop(subtree);
+
+        let message = match panicked.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "component panicked".to_string(),
+            },
+        };
+
+        let mut path = self.cursor.to_vec();
+        path.push(slot);
+        Err(BoundaryError { path, message })
+    }
+
+    /// runs `f` with a Hook pointing to children[slot] of the current StateTree
+    fn descend<R>(&mut self, slot: usize, f: impl FnOnce(&mut Hook) -> R) -> R {
+        let mut cursor = self.cursor.clone();
+        cursor.push(slot);
+
+        let mut child = Hook {
+            cursor,
+            ..Hook::new(self.runtime.clone())
+        };
+        child.pass.claims.by_id = self.pass.claims.by_id;
+
+        let result = f(&mut child);
+        self.finish_child(&mut child);
+        result
+    }
+
+    /// the child is done rendering, so its hook count can be checked right away
+    fn finish_child(&mut self, child: &mut Hook) {
+        let mut tree = self.runtime.lock();
+        let node = tree.get_tree_mut(&child.cursor);
+        self.pass.finish_child(&mut child.pass, node, &child.cursor);
+    }
+
+    /// like use_state, but the value is shared instead of cloned on every read, so it doesn't need
+    /// to implement Clone
+    #[track_caller]
+    pub fn use_state_shared<T>(&mut self, value: T) -> (Arc<T>, impl Fn(T))
+    where
+        T: 'static + MaybeSend + MaybeSync,
+    {
+        let id = self.next_id();
+        let (index, value) = self.runtime.lock().get_state(&self.cursor).use_state_with(
+            &mut self.pass.claims,
+            id,
+            || Arc::new(value),
+        );
+
+        let handle = self.handle(index);
+        let set_value = move |value: T| {
+            handle.update(move |shared: &mut Arc<T>| *shared = Arc::new(value));
         };
 
         (value, set_value)
     }
+
+    /// like use_state_shared, for values behind a trait object such as `Arc<dyn Fn()>`, which
+    /// can't be downcast to otherwise. Every render returns the Arc that got stored last.
+    #[track_caller]
+    pub fn use_state_dyn<T>(&mut self, value: Arc<T>) -> (Arc<T>, Setter<Arc<T>>)
+    where
+        T: 'static + ?Sized + MaybeSend + MaybeSync,
+    {
+        self.use_state(value)
+    }
+
+    /// makes `value` available to this component and all of its children through use_context.
+    /// Children providing a value of the same type shadow this one for their own children.
+    pub fn provide_context<T>(&mut self, value: T)
+    where
+        T: 'static + MaybeSend,
+    {
+        self.provide((TypeId::of::<T>(), None), Box::new(value));
+    }
+
+    /// like provide_context, but the value can only be found through use_context_keyed with the
+    /// same `key`, so several values of the same type can be provided at once
+    pub fn provide_context_keyed<T>(&mut self, key: &ContextKey<T>, value: T)
+    where
+        T: 'static + MaybeSend,
+    {
+        self.provide(key.id(), Box::new(value));
+    }
+
+    fn provide(&mut self, id: ContextId, value: AnyBox) {
+        let mut tree = self.runtime.lock();
+        let node = tree.get_tree_mut(&self.cursor);
+        let previous = node.contexts.insert(id, value);
+        drop(tree);
+
+        // the previous value may own state of its own, so drop it after unlocking
+        drop(previous);
+    }
+
+    /// returns the value of type `T` provided by the closest component up the tree, including
+    /// this one
+    pub fn use_context<T>(&self) -> Option<T>
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        self.find_context((TypeId::of::<T>(), None))
+    }
+
+    /// returns the value provided with provide_context_keyed under `key` by the closest component
+    /// up the tree, including this one
+    pub fn use_context_keyed<T>(&self, key: &ContextKey<T>) -> Option<T>
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        self.find_context(key.id())
+    }
+
+    fn find_context<T: 'static + Clone>(&self, id: ContextId) -> Option<T> {
+        let tree = self.runtime.lock();
+
+        let mut node = &*tree;
+        let mut found = node.contexts.get(&id);
+        for &slot in &self.cursor {
+            node = &node.children[slot];
+            found = node.contexts.get(&id).or(found);
+        }
+
+        found.and_then(|value| value.downcast_ref::<T>()).cloned()
+    }
 }
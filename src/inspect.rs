@@ -0,0 +1,345 @@
+//! read-only access to every register of a StateTree, e.g. for an inspector in devtools or
+//! profiling
+
+use crate::state::StateTree;
+use crate::Runtime;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+
+#[cfg(test)]
+mod tests {
+    use super::{DebugRegistry, RegisterDump};
+    use crate::Runtime;
+
+    #[test]
+    fn dump() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(1u8);
+        hook.with_child(|hook| {
+            let (_, _) = hook.use_state("title");
+            let (_, _) = hook.use_state(2i32);
+            let (_, _) = hook.with_child(|hook| hook.use_state(3i32));
+        });
+
+        let paths: Vec<_> = rt
+            .dump()
+            .into_iter()
+            .map(|register| (register.path, register.index))
+            .collect();
+        assert_eq!(
+            paths,
+            [(vec![], 0), (vec![0], 0), (vec![0], 1), (vec![0, 0], 0)]
+        );
+
+        let mut registry = DebugRegistry::new();
+        registry.register::<i32>();
+        assert_eq!(
+            rt.dump_debug(&registry)[3],
+            RegisterDump {
+                path: vec![0, 0],
+                index: 0,
+                type_name: "i32",
+                value: Some("3".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(0);
+        hook.with_child(|hook| {
+            for _ in 0..3 {
+                let (_, _) = hook.use_state(0);
+            }
+            hook.with_child(|_| ());
+        });
+        hook.with_keyed_child("item", |hook| hook.use_ref(0));
+
+        let stats = rt.stats();
+        assert_eq!(stats.nodes, 4);
+        assert_eq!(stats.registers, 5);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.registers_at(&[]), Some(1));
+        assert_eq!(stats.registers_at(&[0]), Some(3));
+        assert_eq!(stats.registers_at(&[0, 0]), Some(0));
+        assert_eq!(stats.registers_at(&[1]), Some(1));
+        assert_eq!(stats.registers_at(&[2]), None);
+    }
+
+    #[test]
+    fn debug() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(1i32);
+        let (_, _) = hook.with_child(|hook| hook.use_state("child"));
+
+        assert_eq!(
+            format!("{rt:?}"),
+            "Runtime { tree: StateTree { registers: [<i32>], children: [StateTree { registers: [<&str>], children: [] }] }, .. }"
+        );
+
+        let mut registry = DebugRegistry::new();
+        registry.register::<&str>();
+        let debug = format!("{:#?}", rt.debug(&registry));
+        assert!(debug.contains("<i32>"));
+        assert!(debug.contains("\"child\""));
+        assert!(!debug.contains("<&str>"));
+    }
+
+    #[test]
+    fn walk() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(1i32);
+        hook.with_child(|hook| {
+            let (_, _) = hook.use_state("child");
+            let (_, _) = hook.with_child(|hook| hook.use_state(true));
+        });
+        let (_, _) = hook.with_child(|hook| hook.use_state(2u8));
+
+        let mut visited = Vec::new();
+        rt.walk(&mut |path, index, type_name| {
+            visited.push((path.to_vec(), index, type_name.to_string()));
+        });
+        assert_eq!(
+            visited,
+            [
+                (vec![], 0, "i32".to_string()),
+                (vec![0], 0, "&str".to_string()),
+                (vec![0, 0], 0, "bool".to_string()),
+                (vec![1], 0, "u8".to_string()),
+            ]
+        );
+
+        let mut registry = DebugRegistry::new();
+        registry.register::<&str>().register::<bool>();
+
+        let mut values = Vec::new();
+        rt.walk_debug(&registry, &mut |_, _, _, value| {
+            values.push(value.map(str::to_string));
+        });
+        assert_eq!(
+            values,
+            [
+                None,
+                Some("\"child\"".to_string()),
+                Some("true".to_string()),
+                None
+            ]
+        );
+    }
+}
+
+/// size of a StateTree, see StateTree::stats
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    /// number of States, including the root
+    pub nodes: usize,
+    /// number of registers in all of them
+    pub registers: usize,
+    /// length of the longest cursor, so 0 for a tree without children
+    pub max_depth: usize,
+    /// number of registers of each State, by cursor
+    per_node: HashMap<Vec<usize>, usize>,
+}
+
+impl TreeStats {
+    /// number of registers of the State the cursor points to, if there is one
+    pub fn registers_at(&self, cursor: &[usize]) -> Option<usize> {
+        self.per_node.get(cursor).copied()
+    }
+}
+
+/// a register of a StateTree, see Runtime::dump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisterDump {
+    /// cursor of the State holding the register
+    pub path: Vec<usize>,
+    pub index: usize,
+    pub type_name: &'static str,
+    /// the formatted value, if its type is in the DebugRegistry passed to Runtime::dump_debug
+    pub value: Option<String>,
+}
+
+/// called with the cursor, index, type name and formatted value of a register
+pub(crate) type DebugVisitor<'a> = dyn FnMut(&[usize], usize, &str, Option<&str>) + 'a;
+
+/// called with the cursor, index, type name and value of a register
+type AnyVisitor<'a> = dyn FnMut(&[usize], usize, &'static str, &dyn Any) + 'a;
+
+/// knows how to format the types stored in registers, for those that opted in
+#[derive(Default)]
+pub struct DebugRegistry {
+    types: HashMap<TypeId, fn(&dyn Any) -> String>,
+}
+
+impl DebugRegistry {
+    pub fn new() -> Self {
+        DebugRegistry::default()
+    }
+
+    /// makes walk_debug format registers holding a `T`
+    pub fn register<T: 'static + Debug>(&mut self) -> &mut Self {
+        self.types.insert(TypeId::of::<T>(), |value| {
+            format!("{:?}", value.downcast_ref::<T>().unwrap())
+        });
+        self
+    }
+
+    fn format(&self, value: &dyn Any) -> Option<String> {
+        self.types.get(&value.type_id()).map(|format| format(value))
+    }
+}
+
+/// formats a StateTree, the values of registers whose type is in `registry` included
+struct DebugTree<'a> {
+    tree: &'a StateTree,
+    registry: Option<&'a DebugRegistry>,
+}
+
+/// formats the StateTree of a Runtime, locking it meanwhile
+pub(crate) struct DebugRuntime<'a> {
+    pub(crate) runtime: &'a Runtime,
+    pub(crate) registry: Option<&'a DebugRegistry>,
+}
+
+impl Debug for DebugRuntime<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tree = self.runtime.lock();
+        let tree = DebugTree {
+            tree: &tree,
+            registry: self.registry,
+        };
+
+        f.debug_struct("Runtime")
+            .field("tree", &tree)
+            .finish_non_exhaustive()
+    }
+}
+
+/// a formatted value, or its type name in angle brackets
+struct DebugValue(String);
+
+impl Debug for DebugValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Debug for DebugTree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registers: Vec<_> = self
+            .tree
+            .state
+            .read()
+            .iter()
+            .map(|(_, register)| {
+                let value = self.registry.and_then(|r| r.format(register.as_any()));
+                DebugValue(value.unwrap_or_else(|| format!("<{}>", register.type_name)))
+            })
+            .collect();
+
+        let children: Vec<_> = self
+            .tree
+            .children
+            .iter()
+            .map(|tree| DebugTree {
+                tree,
+                registry: self.registry,
+            })
+            .collect();
+
+        f.debug_struct("StateTree")
+            .field("registers", &registers)
+            .field("children", &children)
+            .finish()
+    }
+}
+
+/// shows the type name of every register, see StateTree::debug to see their values
+impl Debug for StateTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        DebugTree {
+            tree: self,
+            registry: None,
+        }
+        .fmt(f)
+    }
+}
+
+impl StateTree {
+    /// like the Debug impl of StateTree, additionally showing the value of registers whose type is
+    /// in `registry`
+    pub fn debug<'a>(&'a self, registry: &'a DebugRegistry) -> impl Debug + 'a {
+        DebugTree {
+            tree: self,
+            registry: Some(registry),
+        }
+    }
+
+    /// calls `visitor` with the cursor, index and type name of every register, visiting a State
+    /// before its children
+    pub fn walk(&self, visitor: &mut dyn FnMut(&[usize], usize, &str)) {
+        self.walk_at(&mut Vec::new(), &mut |path, index, type_name, _| {
+            visitor(path, index, type_name)
+        });
+    }
+
+    /// like walk, additionally passing the value of registers whose type is in `registry`
+    pub fn walk_debug(&self, registry: &DebugRegistry, visitor: &mut DebugVisitor) {
+        self.walk_at(&mut Vec::new(), &mut |path, index, type_name, value| {
+            visitor(path, index, type_name, registry.format(value).as_deref())
+        });
+    }
+
+    /// counts the nodes and registers of this tree, e.g. to find components using way more
+    /// hooks than they should
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        self.stats_at(&mut Vec::new(), &mut stats);
+        stats
+    }
+
+    pub(crate) fn dump(&self, registry: &DebugRegistry) -> Vec<RegisterDump> {
+        let mut dump = Vec::new();
+        self.walk_at(&mut Vec::new(), &mut |path, index, type_name, value| {
+            dump.push(RegisterDump {
+                path: path.to_vec(),
+                index,
+                type_name,
+                value: registry.format(value),
+            })
+        });
+        dump
+    }
+
+    fn stats_at(&self, path: &mut Vec<usize>, stats: &mut TreeStats) {
+        let registers = self.state.read().len();
+        stats.nodes += 1;
+        stats.registers += registers;
+        stats.max_depth = stats.max_depth.max(path.len());
+        stats.per_node.insert(path.clone(), registers);
+
+        for (slot, child) in self.children.iter().enumerate() {
+            path.push(slot);
+            child.stats_at(path, stats);
+            path.pop();
+        }
+    }
+
+    fn walk_at(&self, path: &mut Vec<usize>, visitor: &mut AnyVisitor) {
+        for (index, register) in self.state.read().iter() {
+            visitor(path, index, register.type_name, register.as_any());
+        }
+
+        for (slot, child) in self.children.iter().enumerate() {
+            path.push(slot);
+            child.walk_at(path, visitor);
+            path.pop();
+        }
+    }
+}
@@ -1 +1,49 @@
+// tests live at the top of each module
+#![allow(clippy::items_after_test_module)]
+
+mod clock;
+mod error;
+mod executor;
+#[cfg(feature = "history")]
+mod history;
 mod hook;
+mod inspect;
+#[cfg(feature = "local")]
+mod local;
+mod pass;
+mod runtime;
+#[cfg(feature = "serde")]
+mod snapshot;
+mod state;
+mod sync;
+
+#[cfg(feature = "macros")]
+pub use act2_macros::{component, Component};
+pub use clock::{Clock, SystemClock, TestClock, Wake};
+pub use error::{BoundaryError, HookError, HookOrderError, TreeError};
+#[cfg(not(feature = "thread-local"))]
+pub use executor::ThreadExecutor;
+#[cfg(all(feature = "tokio", not(feature = "thread-local")))]
+pub use executor::TokioExecutor;
+pub use executor::{BlockingExecutor, Executor, Task};
+#[cfg(feature = "history")]
+pub use history::Change;
+#[cfg(not(feature = "thread-local"))]
+pub use hook::Dispatcher;
+pub use hook::{
+    AsyncState, ChildScope, ContextKey, CounterActions, Hook, Path, Setter, StateRef, StoreChanged,
+    Unsubscribe, Updater,
+};
+pub use inspect::{DebugRegistry, RegisterDump, TreeStats};
+#[cfg(feature = "local")]
+pub use local::LocalHook;
+pub use runtime::{
+    reset_global, shutdown, with_isolated_state, Runtime, RuntimeBuilder, SubscriptionId,
+    Transition,
+};
+#[cfg(feature = "json")]
+pub use snapshot::Snapshot;
+#[cfg(feature = "serde")]
+pub use snapshot::{HydrationError, SerializeError, TypeRegistry};
+pub use state::StateTree;
+pub use sync::{MaybeSend, MaybeSync};
@@ -0,0 +1,111 @@
+//! hooks working on a StateTree borrowed for the duration of a render, instead of one shared
+//! through a Runtime. Nothing gets locked and there is no global, which makes them handy for
+//! embedding the state machine somewhere a Runtime doesn't fit, or for testing components.
+//!
+//! This isn't a no_std core: the StateTree it works on is the one of Runtime, which uses
+//! HashMaps and locks from std. Hook doesn't wrap it either, since its setters need the StateTree
+//! shared behind a lock. What both do share is the bookkeeping of a render pass, see RenderPass.
+
+use crate::pass::RenderPass;
+use crate::state::{Register, StateTree};
+use crate::{HookOrderError, MaybeSend};
+use std::panic::Location;
+
+#[cfg(test)]
+mod tests {
+    use super::LocalHook;
+    use crate::StateTree;
+
+    #[test]
+    fn stack_owned_tree() {
+        let mut tree = StateTree::default();
+
+        for expected in 0..3 {
+            let mut hook = LocalHook::new(&mut tree);
+            let count = hook.use_state(|| 0);
+            assert_eq!(*count, expected);
+            *count += 1;
+
+            let nested = hook.with_child(|hook| {
+                let name = hook.use_state(String::new);
+                name.push('a');
+                name.len()
+            });
+            assert_eq!(nested, expected + 1);
+            assert_eq!(hook.end_render(), Ok(()));
+        }
+
+        let mut hook = LocalHook::new(&mut tree);
+        assert!(hook.end_render().is_err());
+    }
+}
+
+/// like Hook, but borrowing the StateTree of the component it renders. State gets handed out by
+/// reference and written to directly, so there are no setters, and no subscribers that could be
+/// notified about changes.
+pub struct LocalHook<'a> {
+    tree: &'a mut StateTree,
+    path: Vec<usize>,
+    pass: RenderPass,
+}
+
+impl<'a> LocalHook<'a> {
+    /// a hook rendering the component whose state is `tree`
+    pub fn new(tree: &'a mut StateTree) -> Self {
+        LocalHook {
+            tree,
+            path: Vec::new(),
+            pass: RenderPass::default(),
+        }
+    }
+
+    /// number of hooks this component used so far
+    pub fn hooks_used(&self) -> usize {
+        self.pass.claims.count
+    }
+
+    /// the state stored by this hook, which gets created with `init` on the first render. Like
+    /// Hook::use_state, it gets found by where it got called from.
+    #[track_caller]
+    pub fn use_state<T>(&mut self, init: impl FnOnce() -> T) -> &mut T
+    where
+        T: 'static + MaybeSend,
+    {
+        let id = self.pass.claims.next(Location::caller());
+
+        let registers = self.tree.state.registers.get_mut();
+        let (index, _) = registers.claim(&mut self.pass.claims, id, || Register::new(init()));
+
+        registers
+            .get_exclusive(index)
+            .expect("the register just got claimed")
+            .get_mut::<T>(index)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// renders a child, which gets its own state like with Hook::with_child
+    pub fn with_child<R>(&mut self, f: impl FnOnce(&mut LocalHook) -> R) -> R {
+        let slot = self.pass.next_child(self.tree);
+
+        let mut path = self.path.clone();
+        path.push(slot);
+        let mut child = LocalHook {
+            path,
+            ..LocalHook::new(&mut self.tree.children[slot])
+        };
+
+        let result = f(&mut child);
+        self.pass
+            .finish_child(&mut child.pass, child.tree, &child.path);
+
+        result
+    }
+
+    /// finishes the render, like Hook::end_render
+    pub fn end_render(&mut self) -> Result<(), HookOrderError> {
+        let unused = self.pass.retain_claimed(self.tree);
+        drop(unused);
+
+        self.pass.finish(self.tree, &self.path)
+    }
+}
@@ -0,0 +1,135 @@
+//! bookkeeping of a single render pass of a component, shared by Hook and LocalHook, so both
+//! match hooks, children and hook counts the same way
+
+use crate::state::{Claims, SharedRegister, StateTree};
+use crate::HookOrderError;
+
+#[cfg(test)]
+mod tests {
+    use super::RenderPass;
+    use crate::StateTree;
+
+    #[test]
+    fn children_by_position() {
+        let mut node = StateTree::default();
+
+        let mut pass = RenderPass::default();
+        assert_eq!(pass.next_child(&mut node), 0);
+        assert_eq!(pass.next_child(&mut node), 1);
+
+        // the same children get visited on the next pass
+        let mut pass = RenderPass::default();
+        assert_eq!(pass.next_child(&mut node), 0);
+        assert_eq!(node.children.len(), 2);
+    }
+
+    #[test]
+    fn hook_count() {
+        let mut node = StateTree::default();
+        let mut parent = RenderPass::default();
+
+        let mut child = RenderPass::default();
+        child.claims.count = 2;
+        parent.finish_child(&mut child, &mut node, &[0]);
+        assert_eq!(parent.finish(&mut StateTree::default(), &[]), Ok(()));
+
+        let mut child = RenderPass::default();
+        child.claims.count = 1;
+        parent.finish_child(&mut child, &mut node, &[0]);
+
+        let error = parent.finish(&mut StateTree::default(), &[]).unwrap_err();
+        assert_eq!((error.path, error.expected, error.actual), (vec![0], 2, 1));
+    }
+}
+
+/// what a component used so far during its current render pass
+#[derive(Default)]
+pub(crate) struct RenderPass {
+    /// the registers claimed by the hooks of this pass
+    pub(crate) claims: Claims,
+    /// position of the next child to be rendered by position
+    pub(crate) child: usize,
+    /// first hook count mismatch of a child rendered during this pass
+    order_error: Option<HookOrderError>,
+}
+
+impl RenderPass {
+    /// slot in `node` of the child rendered next by position, which gets created on its first
+    /// render
+    pub(crate) fn next_child(&mut self, node: &mut StateTree) -> usize {
+        let index = self.child;
+        self.child += 1;
+
+        assert!(index <= node.positional.len());
+        if index == node.positional.len() {
+            node.positional.push(None);
+        }
+
+        // first time this child gets rendered, or the first time since it got unmounted
+        let slot = match node.positional[index] {
+            Some(slot) => slot,
+            None => {
+                let slot = node.mount();
+                node.positional[index] = Some(slot);
+                slot
+            }
+        };
+        node.visit(slot);
+        slot
+    }
+
+    /// the pass of the child at `path` is done, so its hook count can be checked right away.
+    /// `node` is the StateTree of the child.
+    pub(crate) fn finish_child(
+        &mut self,
+        child: &mut RenderPass,
+        node: &mut StateTree,
+        path: &[usize],
+    ) {
+        let error = child
+            .check_hook_count(node, path)
+            .or(child.order_error.take());
+        self.order_error = self.order_error.take().or(error);
+    }
+
+    /// takes the registers of `node` that no hook claimed during this pass out of it. They may
+    /// use state once dropped, so they get returned to be dropped once nothing is locked.
+    pub(crate) fn retain_claimed(&self, node: &mut StateTree) -> Vec<SharedRegister> {
+        node.state.registers.get_mut().retain_claimed(&self.claims)
+    }
+
+    /// fails if the component at `path`, whose StateTree is `node`, or any child rendered during
+    /// this pass used a different number of hooks than on its first render
+    pub(crate) fn finish(
+        &mut self,
+        node: &mut StateTree,
+        path: &[usize],
+    ) -> Result<(), HookOrderError> {
+        match self
+            .check_hook_count(node, path)
+            .or(self.order_error.take())
+        {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// compares the number of hooks used so far with the one of the first render, which gets
+    /// recorded in the StateTree
+    fn check_hook_count(&self, node: &mut StateTree, path: &[usize]) -> Option<HookOrderError> {
+        let actual = self.claims.count;
+
+        match node.hook_count {
+            None => {
+                node.hook_count = Some(actual);
+                None
+            }
+            Some(expected) if expected != actual => Some(HookOrderError {
+                path: path.to_vec(),
+                expected,
+                actual,
+            }),
+            Some(_) => None,
+        }
+    }
+}
@@ -0,0 +1,1584 @@
+use crate::clock::{Clock, SystemClock};
+use crate::executor::{Abortable, Cancel, DefaultExecutor, Executor, Task};
+#[cfg(feature = "history")]
+use crate::history::History;
+use crate::inspect::{DebugRuntime, DebugVisitor};
+use crate::state::{Register, State, StateTree};
+use crate::sync::{Lock, LockGuard, MaybeSend, MaybeSync, Shared, WeakShared};
+use crate::{DebugRegistry, Hook, HookError, RegisterDump, TreeError, TreeStats};
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+#[cfg(not(feature = "thread-local"))]
+use std::sync::Weak;
+#[cfg(not(feature = "tokio"))]
+use std::task::{Poll, Waker};
+
+#[cfg(test)]
+mod tests {
+    use super::Runtime;
+
+    #[cfg(feature = "thread-local")]
+    #[test]
+    fn locked_on_this_thread() {
+        use crate::HookError;
+
+        let rt = Runtime::new();
+        let (_, _) = rt.hook().use_state(1);
+
+        let tree = rt.lock();
+        assert_eq!(rt.try_peek::<i32>(&[], 0), Err(HookError::Locked));
+        drop(tree);
+        assert_eq!(rt.try_peek::<i32>(&[], 0), Ok(1));
+    }
+
+    #[cfg(feature = "raw")]
+    #[test]
+    fn set_raw() {
+        use crate::HookError;
+
+        let rt = Runtime::new();
+        let (_, _) = rt.hook().with_child(|hook| hook.use_state(String::new()));
+        rt.take_dirty();
+
+        let text = Box::new(String::from("restored"));
+        assert_eq!(rt.set_raw(&[0], 0, text, "String"), Ok(()));
+        assert!(rt.take_dirty());
+        let (text, _) = rt.hook().with_child(|hook| hook.use_state(String::new()));
+        assert_eq!(text, "restored");
+
+        assert_eq!(
+            rt.set_raw(&[0], 1, Box::new(1), "i32"),
+            Err(HookError::OutOfBounds { index: 1, len: 1 })
+        );
+        assert!(rt.set_raw(&[1], 0, Box::new(1), "i32").is_err());
+    }
+
+    #[test]
+    fn isolated() {
+        let a = Runtime::new();
+        let b = Runtime::new();
+
+        let (_, set) = a.hook().use_state("a");
+        set("changed");
+        let (_, _) = b.hook().use_state(1);
+
+        let (value, _) = a.hook().use_state("a");
+        assert_eq!(value, "changed");
+        let (value, _) = b.hook().use_state(2);
+        assert_eq!(value, 1);
+
+        // clones share their state
+        let (value, _) = a.clone().hook().use_state("a");
+        assert_eq!(value, "changed");
+    }
+
+    #[test]
+    fn builder() {
+        let rt = Runtime::builder()
+            .push(1u8)
+            .at(&[1])
+            .push(42i32)
+            .push("hi")
+            .build();
+
+        let mut hook = rt.hook();
+        assert_eq!(hook.use_state(0u8).0, 1);
+        let (_, _) = hook.with_child(|hook| hook.use_state(0u8));
+        let (number, text) = hook.with_child(|hook| (hook.use_state(0).0, hook.use_state("").0));
+        assert_eq!((number, text), (42, "hi"));
+    }
+
+    #[test]
+    fn max_hooks() {
+        let rt = Runtime::builder().max_hooks_per_node(3).build();
+        assert_eq!(rt.max_hooks_per_node(), 3);
+
+        let mut hook = rt.hook();
+        for _ in 0..3 {
+            assert!(hook.try_use_state(0).is_ok());
+        }
+        assert_eq!(
+            hook.try_use_state(0).err(),
+            Some(crate::HookError::TooManyHooks {
+                node: vec![],
+                limit: 3
+            })
+        );
+
+        // the limit is per component
+        let (_, _) = hook.with_child(|hook| hook.use_state(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "used more than 3 hooks")]
+    fn max_hooks_any_hook() {
+        let rt = Runtime::builder().max_hooks_per_node(3).build();
+        let mut hook = rt.hook();
+        for i in 0..4 {
+            hook.use_memo(i, || i);
+        }
+    }
+
+    #[cfg(not(feature = "thread-local"))]
+    #[test]
+    fn disjoint_subtrees() {
+        use std::sync::Arc;
+
+        let rt = Arc::new(Runtime::new());
+        std::thread::scope(|s| {
+            for slot in 0..2 {
+                let rt = rt.clone();
+                s.spawn(move || {
+                    let mut hook = rt.hook_at(&[slot]);
+                    for _ in 0..1000 {
+                        hook.begin_render();
+                        let (count, set) = hook.use_state(0);
+                        let (_, set_name) = hook.use_state(String::new());
+                        set(count + 1);
+                        set_name(format!("subtree {slot}"));
+                        hook.end_render().unwrap();
+                    }
+                });
+            }
+        });
+
+        for slot in 0..2 {
+            assert_eq!(rt.peek(&[slot], 0), Some(1000));
+            assert_eq!(rt.peek(&[slot], 1), Some(format!("subtree {slot}")));
+        }
+
+        let mut hook = rt.hook();
+        let (first, _) = hook.with_child(|hook| hook.use_state(0));
+        assert_eq!(first, 1000);
+    }
+
+    #[test]
+    fn middleware() {
+        use super::Transition;
+        use std::sync::{Arc, Mutex};
+
+        let rt = Runtime::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        {
+            let seen = seen.clone();
+            rt.set_middleware(move |transition| seen.lock().unwrap().push(transition.clone()));
+        }
+
+        let mut hook = rt.hook();
+        let (_, set) = hook.use_state(0);
+        let (_, set_name) = hook.with_child(|hook| hook.use_state("name"));
+        set(1);
+        set_name("changed");
+        rt.batch(|| set(2));
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 3);
+        assert_eq!(
+            seen[1],
+            Transition {
+                path: vec![0],
+                index: 0,
+                type_name: "&str",
+                seq: 1
+            }
+        );
+        assert_eq!((seen[2].index, seen[2].seq), (0, 2));
+        drop(seen);
+
+        // a panicking middleware doesn't take the state down with it
+        rt.set_middleware(|_| panic!("middleware failed"));
+        let set = std::panic::AssertUnwindSafe(set);
+        assert!(std::panic::catch_unwind(|| set(3)).is_err());
+        assert!(rt.try_lock().is_ok());
+        assert_eq!(rt.peek(&[], 0), Some(3));
+    }
+
+    #[test]
+    fn cancel_all_tasks() {
+        use crate::{AsyncState, Executor, Task};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        /// keeps tasks around without ever running them
+        #[derive(Clone, Default)]
+        struct Deferred(Arc<Mutex<Vec<Task>>>);
+
+        impl Executor for Deferred {
+            fn spawn(&self, task: Task) {
+                self.0.lock().unwrap().push(task);
+            }
+        }
+
+        /// counts how many futures got dropped
+        struct Cleanup(Arc<AtomicUsize>);
+
+        impl Drop for Cleanup {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let executor = Deferred::default();
+        let rt = Runtime::with_executor(executor.clone());
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let render = || {
+            let mut hook = rt.hook();
+            let mut spawn = |deps: u8| {
+                let cleanup = Cleanup(dropped.clone());
+                hook.use_async(deps, move || async move {
+                    let _cleanup = cleanup;
+                    std::future::pending::<Result<u8, ()>>().await
+                })
+            };
+            (spawn(1), spawn(2))
+        };
+
+        assert_eq!(render(), (AsyncState::Loading, AsyncState::Loading));
+        assert_eq!(rt.pending_tasks(), 2);
+
+        rt.cancel_all_tasks();
+        assert_eq!(rt.pending_tasks(), 0);
+        assert_eq!(dropped.load(Ordering::SeqCst), 2);
+
+        // the executor still has the tasks, which complete right away now
+        for task in executor.0.lock().unwrap().drain(..) {
+            crate::executor::block_on(task);
+        }
+
+        // intervals count as well, and stop once unmounted
+        rt.hook()
+            .with_child(|hook| hook.use_interval(Duration::from_secs(60), || {}));
+        assert_eq!(rt.pending_tasks(), 1);
+        rt.hook().unmount_child(0);
+        assert_eq!(rt.pending_tasks(), 0);
+    }
+
+    #[test]
+    fn render() {
+        let rt = Runtime::new();
+        let app = |hook: &mut crate::Hook| {
+            let (count, set) = hook.use_state(0);
+            set(count + 1);
+            count
+        };
+
+        assert_eq!(rt.render(app), 0);
+        assert_eq!(rt.render(app), 1);
+    }
+
+    #[test]
+    fn isolated_state() {
+        use super::with_isolated_state;
+
+        let outer = with_isolated_state(|| {
+            let (_, set) = crate::Hook::default().use_state(1);
+            set(2);
+
+            let inner = with_isolated_state(|| crate::Hook::default().use_state(3).0);
+            assert_eq!(inner, 3);
+            crate::Hook::default().use_state(0).0
+        });
+        assert_eq!(outer, 2);
+
+        let panicked = std::panic::catch_unwind(|| {
+            with_isolated_state(|| {
+                let (_, _) = crate::Hook::default().use_state("isolated");
+                panic!("test failed");
+            })
+        });
+        assert!(panicked.is_err());
+        with_isolated_state(|| assert_eq!(crate::Hook::default().use_state(4).0, 4));
+    }
+
+    #[test]
+    fn async_batch() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        /// pending once, so the writes before and after it happen in different polls
+        fn yield_now() -> impl Future<Output = ()> {
+            let mut yielded = false;
+            std::future::poll_fn(move |_| {
+                if std::mem::replace(&mut yielded, true) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+        }
+
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, set_a) = hook.use_state(0);
+        let (_, set_b) = hook.use_state(0);
+        let render = |a: i32, b: i32| {
+            let (set_a, set_b) = (set_a.clone(), set_b.clone());
+            async move {
+                set_a(a);
+                yield_now().await;
+                set_b(b);
+                a + b
+            }
+        };
+        let state = || (rt.peek::<i32>(&[], 0), rt.peek::<i32>(&[], 1));
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let mut batch = Box::pin(rt.async_batch(render(1, 2)));
+        assert!(batch.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(state(), (Some(0), Some(0)));
+        assert_eq!(batch.as_mut().poll(&mut cx), Poll::Ready(3));
+        assert_eq!(state(), (Some(1), Some(2)));
+
+        // a future dropped halfway through doesn't apply any of its writes
+        let mut batch = Box::pin(rt.async_batch(render(5, 6)));
+        assert!(batch.as_mut().poll(&mut cx).is_pending());
+        drop(batch);
+        assert_eq!(state(), (Some(1), Some(2)));
+
+        // writes outside of the future aren't affected
+        set_a(7);
+        assert_eq!(state(), (Some(7), Some(2)));
+    }
+
+    #[test]
+    fn replace_subtree() {
+        use super::RuntimeBuilder;
+        use crate::{StateTree, TreeError};
+
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        hook.with_child(|hook| {
+            let (_, _) = hook.use_state("home");
+            let (_, _) = hook.with_child(|hook| hook.use_state(1));
+        });
+        let (_, set) = hook.with_child(|hook| hook.use_state("sidebar"));
+        set("open");
+
+        let settings = RuntimeBuilder::new().push("settings").build_tree();
+        rt.replace_subtree(&[0], settings).unwrap();
+
+        let mut hook = rt.hook();
+        let (page, _) = hook.with_child(|hook| hook.use_state("home"));
+        let (sidebar, _) = hook.with_child(|hook| hook.use_state("sidebar"));
+        assert_eq!(page, "settings");
+        assert_eq!(sidebar, "open");
+        assert_eq!(rt.peek::<i32>(&[0, 0], 0), None);
+
+        assert_eq!(
+            rt.replace_subtree(&[0, 3], StateTree::default()),
+            Err(TreeError {
+                depth: 1,
+                index: 3,
+                len: 0
+            })
+        );
+    }
+
+    #[test]
+    fn peek() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(0u8);
+        hook.with_child(|hook| {
+            let (_, set) = hook.use_state("closed");
+            set("open");
+        });
+
+        assert_eq!(rt.peek(&[0], 0), Some("open"));
+        assert_eq!(rt.peek::<u8>(&[0], 0), None);
+        assert_eq!(rt.peek::<&str>(&[0], 1), None);
+        assert_eq!(rt.peek::<&str>(&[1], 0), None);
+        assert_eq!(rt.peek(&[], 0), Some(0u8));
+    }
+
+    #[test]
+    fn shutdown() {
+        use std::sync::{Arc, Mutex};
+
+        /// records its name once dropped
+        #[derive(Clone)]
+        struct Socket(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+        impl Drop for Socket {
+            fn drop(&mut self) {
+                self.1.lock().unwrap().push(self.0);
+            }
+        }
+
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let _ = hook.use_ref(Socket("parent", closed.clone()));
+        hook.with_child(|hook| {
+            let _ = hook.use_ref(Socket("child", closed.clone()));
+        });
+
+        rt.shutdown();
+        assert_eq!(*closed.lock().unwrap(), ["child", "parent"]);
+    }
+
+    #[test]
+    fn changed() {
+        let rt = Runtime::new();
+        let (_, set) = rt.hook().use_state(0);
+
+        // a change before anyone waits isn't missed
+        set(1);
+        crate::executor::block_on(Box::pin(rt.changed()));
+
+        #[cfg(not(feature = "thread-local"))]
+        {
+            let writer = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                set(2);
+            });
+
+            crate::executor::block_on(Box::pin(rt.changed()));
+            assert_eq!(rt.hook().use_state(0).0, 2);
+            writer.join().unwrap();
+        }
+    }
+
+    #[cfg(all(feature = "tokio", not(feature = "thread-local")))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn changed_tokio() {
+        let rt = Runtime::new();
+        let (_, set) = rt.hook().use_state(0);
+
+        set(1);
+        rt.changed().await;
+
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            set(2);
+        });
+
+        rt.changed().await;
+        assert_eq!(rt.hook().use_state(0).0, 2);
+        writer.await.unwrap();
+    }
+
+    #[cfg(feature = "thread-local")]
+    #[test]
+    fn global_per_thread() {
+        let (_, set) = Runtime::global().hook().use_state(0);
+        set(1);
+
+        std::thread::spawn(|| {
+            let (value, _) = Runtime::global().hook().use_state(0);
+            assert_eq!(value, 0);
+        })
+        .join()
+        .unwrap();
+
+        let (value, _) = Runtime::global().hook().use_state(0);
+        assert_eq!(value, 1);
+    }
+
+    #[test]
+    fn batch() {
+        let rt = Runtime::new();
+
+        let mut hook = rt.hook();
+        let (_, set_a) = hook.use_state(1);
+        let (_, set_b) = hook.use_state("b");
+        let (_, dispatch) = hook.use_reducer(10, |count: i32, add: i32| count + add);
+
+        let read = || {
+            let mut hook = rt.hook();
+            let (a, _) = hook.use_state(0);
+            let (b, _) = hook.use_state("");
+            let (c, _) = hook.use_reducer(0, |count: i32, add: i32| count + add);
+            (a, b, c)
+        };
+
+        let during = rt.batch(|| {
+            set_a(2);
+            set_b("changed");
+            dispatch(5);
+            rt.batch(|| dispatch(5));
+            read()
+        });
+
+        assert_eq!(during, (1, "b", 10));
+        assert_eq!(read(), (2, "changed", 20));
+
+        // writes of a panicking batch get dropped
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            rt.batch(|| {
+                set_a(3);
+                panic!("event handler went wrong");
+            })
+        }));
+        assert!(panicked.is_err());
+        set_b("after");
+        assert_eq!(read(), (2, "after", 20));
+    }
+
+    #[test]
+    fn batch_every_setter() {
+        let rt = Runtime::new();
+        let read = || {
+            let mut hook = rt.hook();
+            let (a, _) = hook.use_state(0);
+            let (b, _) = hook.use_state_fn(0);
+            let (c, _) = hook.use_state_eq(0);
+            (a, b, c)
+        };
+
+        let mut hook = rt.hook();
+        let (_, set_a) = hook.use_state(1);
+        let (_, update_b) = hook.use_state_fn(10);
+        let (_, set_c) = hook.use_state_eq(100);
+
+        let during = rt.batch(|| {
+            set_a.update_with(|a| *a *= 10);
+            update_b.update(|b| b + 1);
+            update_b.update(|b| b * 2);
+            read()
+        });
+
+        assert_eq!(during, (1, 10, 100));
+        assert_eq!(read(), (10, 22, 100));
+
+        // setters comparing with the current value apply right away, and see each other
+        let during = rt.batch(|| {
+            assert_eq!(set_a.replace(2), 10);
+            assert!(set_a.compare_and_set(2, 3));
+            assert!(set_c(101));
+            assert!(set_c(100));
+            assert!(!set_c(100));
+            read()
+        });
+
+        assert_eq!(during, (3, 22, 100));
+        assert_eq!(read(), (3, 22, 100));
+    }
+
+    #[cfg(all(feature = "tokio", not(feature = "thread-local")))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn async_batch_spawned() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, set_a) = hook.use_state(0);
+        let (_, set_b) = hook.use_state(0);
+        let state = {
+            let rt = rt.clone();
+            move || (rt.peek::<i32>(&[], 0), rt.peek::<i32>(&[], 1))
+        };
+
+        let (resume, resumed) = tokio::sync::oneshot::channel();
+        let (paused, pause) = tokio::sync::oneshot::channel();
+        let batch = tokio::spawn(rt.async_batch(async move {
+            set_a(1);
+            set_b(2);
+            paused.send(()).unwrap();
+            resumed.await.unwrap();
+            set_b.update_with(|b| *b += 1);
+        }));
+
+        // the future is halfway through, and none of its writes got applied yet
+        pause.await.unwrap();
+        assert_eq!(state(), (Some(0), Some(0)));
+
+        resume.send(()).unwrap();
+        batch.await.unwrap();
+        assert_eq!(state(), (Some(1), Some(3)));
+    }
+
+    #[test]
+    fn subscribe() {
+        use std::sync::{Arc, Mutex};
+
+        let rt = Runtime::new();
+        let (_, set) = rt.hook().use_state(0);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let id = {
+            let (reader, seen) = (rt.clone(), seen.clone());
+            // reading state would deadlock if the StateTree was still locked
+            rt.subscribe(move || seen.lock().unwrap().push(reader.hook().use_state(0).0))
+        };
+
+        set(1);
+        assert_eq!(*seen.lock().unwrap(), [1]);
+
+        rt.batch(|| {
+            set(2);
+            set(3);
+        });
+        assert_eq!(*seen.lock().unwrap(), [1, 3]);
+
+        rt.unsubscribe(id);
+        set(4);
+        assert_eq!(*seen.lock().unwrap(), [1, 3]);
+    }
+
+    #[test]
+    fn take_dirty() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, set) = hook.use_state(0);
+        let (_, set_child) = hook.with_child(|hook| hook.use_state_eq(0));
+
+        assert!(!rt.take_dirty());
+        set(1);
+        assert!(rt.take_dirty());
+        assert!(!rt.take_dirty());
+
+        set_child(0);
+        assert!(!rt.take_dirty());
+        set_child(1);
+        assert!(rt.take_dirty());
+    }
+
+    #[test]
+    fn reset() {
+        let rt = Runtime::new();
+        let (_, set) = rt.hook().use_state(1);
+        set(2);
+        let (_, set) = rt.hook().with_child(|hook| hook.use_state("child"));
+        set("changed");
+
+        rt.reset();
+        assert_eq!(rt.hook().use_state(1).0, 1);
+        assert_eq!(
+            rt.hook().with_child(|hook| hook.use_state("child")).0,
+            "child"
+        );
+    }
+
+    #[test]
+    fn stale_child_setter_after_reset() {
+        let rt = Runtime::new();
+        let (_, set) = rt.hook().with_child(|hook| hook.use_state(1));
+
+        rt.reset();
+        set(2);
+        assert!(!set.compare_and_set(1, 3));
+        rt.batch(|| set(4));
+
+        assert_eq!(rt.lock().children.len(), 0);
+        assert_eq!(rt.hook().with_child(|hook| hook.use_state(5)).0, 5);
+    }
+}
+
+#[cfg(not(feature = "thread-local"))]
+lazy_static::lazy_static! {
+    static ref GLOBAL: Runtime = Runtime::new();
+}
+
+#[cfg(feature = "thread-local")]
+thread_local! {
+    static GLOBAL: Runtime = Runtime::new();
+}
+
+/// a write to some State, deferred until the end of a batch. Send, so an async_batch can move to
+/// another thread along with its writes.
+#[cfg(not(feature = "thread-local"))]
+type Write = Box<dyn FnOnce(&StateTree) -> Option<Transition> + Send>;
+
+#[cfg(feature = "thread-local")]
+type Write = Box<dyn FnOnce(&StateTree) -> Option<Transition>>;
+
+/// identifies the StateTree shared by all clones of a runtime
+type RuntimeId = *const Lock<StateTree>;
+
+#[cfg(not(feature = "thread-local"))]
+type Subscriber = Shared<dyn Fn() + Send + Sync>;
+
+#[cfg(feature = "thread-local")]
+type Subscriber = Shared<dyn Fn()>;
+
+#[cfg(not(feature = "thread-local"))]
+type Middleware = Shared<dyn Fn(&Transition) + Send + Sync>;
+
+#[cfg(feature = "thread-local")]
+type Middleware = Shared<dyn Fn(&Transition)>;
+
+/// a write of a setter to a register, see Runtime::set_middleware
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transition {
+    /// cursor of the State holding the register
+    pub path: Vec<usize>,
+    pub index: usize,
+    /// name of the type of the value written
+    pub type_name: &'static str,
+    /// number of writes to the register so far, see Setter::last_seq
+    pub seq: u64,
+}
+
+impl Transition {
+    /// describes the last write to the register at `index` of `state`, None if there is no such
+    /// register
+    pub(crate) fn of(state: &State, path: &[usize], index: usize) -> Option<Self> {
+        let registers = state.read();
+        let register = registers.get(index)?;
+        Some(Transition {
+            path: path.to_vec(),
+            index,
+            type_name: register.type_name,
+            seq: register.seq,
+        })
+    }
+}
+
+/// identifies a callback registered with Runtime::subscribe
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(usize);
+
+#[derive(Default)]
+struct Subscribers {
+    next: usize,
+    list: Vec<(SubscriptionId, Subscriber)>,
+    /// whether the state changed since a future returned by Runtime::changed last completed
+    #[cfg(not(feature = "tokio"))]
+    changed: bool,
+    /// futures of Runtime::changed waiting for a change
+    #[cfg(not(feature = "tokio"))]
+    waiting: Vec<Waker>,
+    /// wakes a future of Runtime::changed, or keeps a permit for the next one if none waits
+    #[cfg(feature = "tokio")]
+    changed: Shared<tokio::sync::Notify>,
+    /// see Runtime::set_middleware
+    middleware: Vec<Middleware>,
+}
+
+thread_local! {
+    /// writes deferred by Runtime::batch on this thread, for each runtime currently batching
+    static BATCHES: RefCell<Vec<(RuntimeId, Vec<Write>)>> = const { RefCell::new(Vec::new()) };
+
+    /// runtimes standing in for the global one on this thread, see with_isolated_state
+    static ISOLATED: RefCell<Vec<Runtime>> = const { RefCell::new(Vec::new()) };
+}
+
+/// owns a StateTree, which all Hooks created by the runtime read from. Clones of a runtime share
+/// the same state.
+///
+/// Unless the `thread-local` feature is enabled, a runtime is Send and Sync, so its clones can be
+/// used from any number of threads, e.g. with each thread rendering a subtree of its own through
+/// hook_at. Every read and write of state locks the whole StateTree, so threads never observe
+/// half done writes, but renders of different threads do interleave. Threads rendering disjoint
+/// subtrees don't affect each other, while threads rendering the same component do.
+#[derive(Clone)]
+pub struct Runtime {
+    tree: Shared<Lock<StateTree>>,
+    subscribers: Shared<Lock<Subscribers>>,
+    clock: Shared<dyn Clock>,
+    executor: Shared<dyn Executor>,
+    /// see pending_tasks
+    tasks: Shared<Lock<Vec<WeakShared<dyn Cancel>>>>,
+    /// see max_hooks_per_node
+    max_hooks: usize,
+    #[cfg(feature = "history")]
+    history: Shared<Lock<History>>,
+}
+
+/// number of hooks a component may use unless RuntimeBuilder::max_hooks_per_node says otherwise
+const DEFAULT_MAX_HOOKS: usize = 10_000;
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Runtime {
+            tree: Shared::default(),
+            subscribers: Shared::default(),
+            clock: Shared::new(SystemClock),
+            executor: Shared::new(DefaultExecutor::default()),
+            tasks: Shared::default(),
+            max_hooks: DEFAULT_MAX_HOOKS,
+            #[cfg(feature = "history")]
+            history: Shared::default(),
+        }
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Runtime::default()
+    }
+
+    /// seeds the state of a runtime before its first render, see RuntimeBuilder
+    pub fn builder() -> RuntimeBuilder {
+        RuntimeBuilder::new()
+    }
+
+    /// a runtime whose time-based hooks like use_debounce ask `clock` for the time, e.g. a
+    /// TestClock in tests
+    pub fn with_clock(clock: impl Clock + 'static) -> Runtime {
+        Runtime {
+            clock: Shared::new(clock),
+            ..Runtime::default()
+        }
+    }
+
+    pub(crate) fn clock(&self) -> &Shared<dyn Clock> {
+        &self.clock
+    }
+
+    /// a runtime spawning the futures of use_async on `executor`
+    pub fn with_executor(executor: impl Executor + 'static) -> Runtime {
+        Runtime {
+            executor: Shared::new(executor),
+            ..Runtime::default()
+        }
+    }
+
+    /// spawns `task` on the executor, keeping track of it until it completes, so it can be
+    /// cancelled with cancel_all_tasks
+    pub(crate) fn spawn(&self, task: Task) {
+        let task = Shared::new(Abortable::new(task));
+        self.track(&task);
+        self.executor
+            .spawn(Box::pin(std::future::poll_fn(move |cx| task.poll(cx))));
+    }
+
+    /// counts `task` as pending until it finished, e.g. the flag stopping an interval. Only a
+    /// weak reference is kept, so tasks dropped by their owner don't count anymore.
+    pub(crate) fn track<C: Cancel + 'static>(&self, task: &Shared<C>) {
+        let task = Shared::downgrade(task);
+        self.tasks.lock().push(task);
+    }
+
+    /// the number of futures of use_async and intervals of use_interval that are still running
+    pub fn pending_tasks(&self) -> usize {
+        let mut tasks = self.tasks.lock();
+        tasks.retain(|task| task.upgrade().is_some_and(|task| !task.finished()));
+        tasks.len()
+    }
+
+    /// stops every pending task. Futures get dropped right away, even if the executor holds on
+    /// to them, and intervals don't tick anymore. Their results never reach the state.
+    pub fn cancel_all_tasks(&self) {
+        let tasks: Vec<_> = std::mem::take(&mut *self.tasks.lock())
+            .iter()
+            .filter_map(WeakShared::upgrade)
+            .collect();
+
+        // dropping futures may use state, so the tasks can't be locked while cancelling them
+        for task in tasks {
+            task.cancel();
+        }
+    }
+
+    /// how many hooks a single component may use. Beyond that, they fail with
+    /// HookError::TooManyHooks instead of allocating ever more registers, so a component calling
+    /// them in a loop doesn't eat up all memory.
+    pub fn max_hooks_per_node(&self) -> usize {
+        self.max_hooks
+    }
+
+    /// the runtime used by Hook::default(), for apps that only need a single one. With the
+    /// `thread-local` feature, every thread has its own global runtime. Inside of
+    /// with_isolated_state, this is the runtime it installed instead.
+    pub fn global() -> Runtime {
+        if let Some(isolated) = ISOLATED.with(|isolated| isolated.borrow().last().cloned()) {
+            return isolated;
+        }
+
+        #[cfg(not(feature = "thread-local"))]
+        return GLOBAL.clone();
+
+        #[cfg(feature = "thread-local")]
+        return GLOBAL.with(Runtime::clone);
+    }
+
+    /// throws away all state, including that of every child. Cleanups of effects get run.
+    pub fn reset(&self) {
+        let fresh = StateTree::default();
+        fresh.state.mark_dirty();
+        let old = std::mem::replace(&mut *self.lock(), fresh);
+
+        #[cfg(feature = "history")]
+        let history = std::mem::take(&mut *self.history.lock());
+
+        // dropping the old state runs cleanups, which might use state themselves
+        drop(old);
+        #[cfg(feature = "history")]
+        drop(history);
+
+        self.notify();
+    }
+
+    /// drops all state right away, running the Drop impls of every stored value and the cleanups
+    /// of every effect, children before their parents. Subscribers get dropped as well, without
+    /// being notified. A runtime drops its state once the last clone is gone anyway, this is
+    /// mostly for the global runtime, which lives until the program exits and so never does.
+    /// Pending tasks get cancelled first, see cancel_all_tasks.
+    pub fn shutdown(&self) {
+        self.cancel_all_tasks();
+        let tree = std::mem::take(&mut *self.lock());
+        let subscribers = std::mem::take(&mut *self.subscribers.lock());
+
+        #[cfg(feature = "history")]
+        let history = std::mem::take(&mut *self.history.lock());
+
+        // dropping the state runs cleanups, which might use state themselves
+        drop(tree);
+        drop(subscribers);
+        #[cfg(feature = "history")]
+        drop(history);
+    }
+
+    /// a handle to this runtime that doesn't keep its state alive
+    #[cfg(not(feature = "thread-local"))]
+    pub(crate) fn downgrade(&self) -> WeakRuntime {
+        WeakRuntime {
+            tree: Shared::downgrade(&self.tree),
+            subscribers: Shared::downgrade(&self.subscribers),
+            clock: self.clock.clone(),
+            executor: self.executor.clone(),
+            tasks: self.tasks.clone(),
+            max_hooks: self.max_hooks,
+            #[cfg(feature = "history")]
+            history: Shared::downgrade(&self.history),
+        }
+    }
+
+    /// returns a hook pointing to the root of this runtime's StateTree
+    pub fn hook(&self) -> Hook {
+        Hook::new(self.clone())
+    }
+
+    /// renders the root component `f` with a fresh hook, from begin_render to end_render, and
+    /// returns what `f` returned. Panics if `f` used a different number of hooks than on its
+    /// first render, like a #[component] does.
+    pub fn render<R>(&self, f: impl FnOnce(&mut Hook) -> R) -> R {
+        let mut hook = self.hook();
+        hook.begin_render();
+        let output = f(&mut hook);
+        if let Err(e) = hook.end_render() {
+            panic!("{}", e);
+        }
+        output
+    }
+
+    /// returns a hook pointing to the State at `cursor`, e.g. to render a subtree on a thread of
+    /// its own. Children on the way get created if needed, like with RuntimeBuilder::at.
+    pub fn hook_at(&self, cursor: &[usize]) -> Hook {
+        self.lock().get_tree_or_insert(cursor);
+        Hook::at(self.clone(), cursor)
+    }
+
+    /// a copy of the register at `index` of the State at `cursor`, e.g. for a parent to look at
+    /// the state of a child. None if there is no such State or register, or if it doesn't hold a
+    /// `T`. Doesn't count as using a hook.
+    pub fn peek<T: Clone + 'static>(&self, cursor: &[usize], index: usize) -> Option<T> {
+        self.try_peek(cursor, index).ok()
+    }
+
+    /// like peek, but tells why there is no value
+    pub fn try_peek<T: Clone + 'static>(
+        &self,
+        cursor: &[usize],
+        index: usize,
+    ) -> Result<T, HookError> {
+        let tree = self.try_lock()?;
+        tree.try_get_state(cursor)?.read_state(index, T::clone)
+    }
+
+    /// name of the type stored in the register at `index` of the State at `cursor`, e.g. for a
+    /// debugger to pick an editor for it, without reading the value. None if there is no such
+    /// State or register.
+    pub fn type_name_at(&self, cursor: &[usize], index: usize) -> Option<&'static str> {
+        self.lock().try_get_state(cursor).ok()?.type_name_at(index)
+    }
+
+    /// replaces the value of the register at `index` of the State at `cursor` with `value`
+    /// without looking at its type, e.g. for a restore layer installing values it deserialized
+    /// itself. `type_name` is what type mismatches report as found. If `value` isn't of the type
+    /// the hook using the register asks for, its next read fails with HookError::TypeMismatch,
+    /// which most hooks turn into a panic.
+    #[cfg(feature = "raw")]
+    pub fn set_raw(
+        &self,
+        cursor: &[usize],
+        index: usize,
+        value: crate::state::AnyBox,
+        type_name: &'static str,
+    ) -> Result<(), HookError> {
+        let tree = self.try_lock()?;
+        let previous = tree
+            .try_get_state(cursor)?
+            .set_raw(index, value, type_name)?;
+        drop(tree);
+
+        // the previous value may use state once dropped
+        drop(previous);
+        self.notify();
+        Ok(())
+    }
+
+    /// every register of this runtime, in the order StateTree::walk visits them
+    pub fn dump(&self) -> Vec<RegisterDump> {
+        self.dump_debug(&DebugRegistry::default())
+    }
+
+    /// like dump, additionally formatting the values of registers whose type is in `registry`
+    pub fn dump_debug(&self, registry: &DebugRegistry) -> Vec<RegisterDump> {
+        self.lock().dump(registry)
+    }
+
+    /// calls `visitor` for every register of this runtime, see StateTree::walk. The state stays
+    /// locked meanwhile, so `visitor` must not use this runtime.
+    pub fn walk(&self, visitor: &mut dyn FnMut(&[usize], usize, &str)) {
+        self.lock().walk(visitor);
+    }
+
+    /// like walk, additionally passing the value of registers whose type is in `registry`
+    pub fn walk_debug(&self, registry: &DebugRegistry, visitor: &mut DebugVisitor) {
+        self.lock().walk_debug(registry, visitor);
+    }
+
+    /// counts the nodes and registers of this runtime, see StateTree::stats
+    pub fn stats(&self) -> TreeStats {
+        self.lock().stats()
+    }
+
+    /// like the Debug impl of Runtime, additionally showing the value of registers whose type is
+    /// in `registry`
+    pub fn debug<'a>(&'a self, registry: &'a DebugRegistry) -> impl fmt::Debug + 'a {
+        DebugRuntime {
+            runtime: self,
+            registry: Some(registry),
+        }
+    }
+
+    /// serializes the whole StateTree of this runtime to JSON
+    #[cfg(feature = "json")]
+    pub fn to_json(&self, registry: &crate::TypeRegistry) -> Result<String, crate::SerializeError> {
+        self.lock().to_json(registry)
+    }
+
+    /// serializes the whole StateTree of this runtime to a compact binary format, see
+    /// StateTree::to_bincode
+    #[cfg(feature = "bincode")]
+    pub fn to_bincode(
+        &self,
+        registry: &crate::TypeRegistry,
+    ) -> Result<Vec<u8>, crate::SerializeError> {
+        self.lock().to_bincode(registry)
+    }
+
+    /// copies the state of the subtree at `cursor`, e.g. to roll back an optimistic update with
+    /// restore_subtree. Only types in `registry` can be copied, and there has to be a subtree at
+    /// `cursor`.
+    #[cfg(feature = "json")]
+    pub fn snapshot_subtree(
+        &self,
+        cursor: &[usize],
+        registry: &crate::TypeRegistry,
+    ) -> Result<crate::Snapshot, crate::SerializeError> {
+        crate::Snapshot::take(&self.lock(), cursor, registry)
+    }
+
+    /// replaces the subtree at `cursor` with a snapshot taken from there, leaving the rest of the
+    /// tree alone
+    #[cfg(feature = "json")]
+    pub fn restore_subtree(
+        &self,
+        cursor: &[usize],
+        snapshot: crate::Snapshot,
+        registry: &crate::TypeRegistry,
+    ) -> Result<(), crate::HydrationError> {
+        let restored = snapshot.restore(cursor, registry)?;
+        restored.state.mark_dirty();
+        let mut tree = self.try_lock()?;
+        let node = tree.try_get_tree_mut(cursor).map_err(HookError::from)?;
+        let replaced = std::mem::replace(node, restored);
+        drop(tree);
+
+        // cleanups may use state themselves, so they can only run once the tree is unlocked
+        drop(replaced);
+        self.notify();
+        Ok(())
+    }
+
+    /// swaps the subtree at `cursor` for `tree` at once, e.g. to switch pages in a router. The old
+    /// subtree gets dropped, running its cleanups. Fails if there is nothing at `cursor` yet.
+    pub fn replace_subtree(&self, cursor: &[usize], tree: StateTree) -> Result<(), TreeError> {
+        tree.state.mark_dirty();
+        let replaced = std::mem::replace(self.lock().try_get_tree_mut(cursor)?, tree);
+
+        // cleanups may use state themselves, so they can only run once the tree is unlocked
+        drop(replaced);
+        self.notify();
+        Ok(())
+    }
+
+    /// a runtime remembering the last `capacity` writes of use_state setters, instead of the
+    /// default of 100
+    #[cfg(feature = "history")]
+    pub fn with_history_capacity(capacity: usize) -> Runtime {
+        Runtime {
+            history: Shared::new(Lock::new(History::with_capacity(capacity))),
+            ..Runtime::default()
+        }
+    }
+
+    /// the writes that can be undone or redone, oldest first
+    #[cfg(feature = "history")]
+    pub fn history(&self) -> Vec<crate::Change> {
+        self.history.lock().changes()
+    }
+
+    /// reverts the most recent write that isn't undone yet. Returns false if there is none.
+    #[cfg(feature = "history")]
+    pub fn undo(&self) -> bool {
+        let tree = self.lock();
+        let changed = self.history.lock().undo(&tree);
+        drop(tree);
+
+        if changed {
+            self.notify();
+        }
+        changed
+    }
+
+    /// reapplies the last undone write. Returns false if there is none, or if some write happened
+    /// since the undo.
+    #[cfg(feature = "history")]
+    pub fn redo(&self) -> bool {
+        let tree = self.lock();
+        let changed = self.history.lock().redo(&tree);
+        drop(tree);
+
+        if changed {
+            self.notify();
+        }
+        changed
+    }
+
+    /// remembers the value register `index` of the State at `cursor` held before a write. Gets
+    /// called while the StateTree is locked.
+    #[cfg(feature = "history")]
+    pub(crate) fn record(
+        &self,
+        cursor: Vec<usize>,
+        index: usize,
+        type_name: &'static str,
+        previous: crate::state::Register,
+    ) {
+        self.history
+            .lock()
+            .record(cursor, index, type_name, previous);
+    }
+
+    /// whether any state got changed since the last call, e.g. to skip rendering a frame if
+    /// nothing happened. Setters that skip equal values don't count as a change.
+    pub fn take_dirty(&self) -> bool {
+        self.lock().take_dirty()
+    }
+
+    /// calls `f` after every change to the state of this runtime, e.g. to schedule a render. The
+    /// StateTree is unlocked by then, so `f` may read state. Changes made by a batch only notify
+    /// once the batch is done.
+    pub fn subscribe(&self, f: impl Fn() + MaybeSend + MaybeSync + 'static) -> SubscriptionId {
+        let mut subscribers = self.subscribers.lock();
+
+        let id = SubscriptionId(subscribers.next);
+        subscribers.next += 1;
+        subscribers.list.push((id, Shared::new(f)));
+        id
+    }
+
+    /// calls `f` with every write of a setter to a register, e.g. to log them. Middleware gets
+    /// called in the order it got added in, after the write and before subscribers get notified.
+    /// The StateTree is unlocked by then, so a panicking middleware can't poison it. Writes to
+    /// registers of use_state_named aren't passed on, as they don't have an index.
+    pub fn set_middleware(&self, f: impl Fn(&Transition) + MaybeSend + MaybeSync + 'static) {
+        self.subscribers.lock().middleware.push(Shared::new(f));
+    }
+
+    /// passes `transitions` to every middleware. Must not be called while holding the lock of
+    /// the StateTree.
+    pub(crate) fn transition(&self, transitions: impl IntoIterator<Item = Transition>) {
+        // middleware may add middleware itself
+        let middleware = self.subscribers.lock().middleware.clone();
+        for transition in transitions {
+            for f in &middleware {
+                f(&transition);
+            }
+        }
+    }
+
+    /// stops calling a callback registered with subscribe
+    pub fn unsubscribe(&self, id: SubscriptionId) {
+        self.subscribers
+            .lock()
+            .list
+            .retain(|(other, _)| *other != id);
+    }
+
+    /// resolves once the state changed, e.g. for an async render loop like
+    /// `loop { render(); rt.changed().await; }`. Changes made after the previous future resolved
+    /// are remembered, so a change that happened during the render still counts, even though
+    /// nothing was waiting yet.
+    ///
+    /// With the `tokio` feature this waits on a tokio::sync::Notify, so a change wakes a single
+    /// waiting future.
+    #[cfg(feature = "tokio")]
+    pub fn changed(&self) -> impl Future<Output = ()> {
+        let changed = self.subscribers.lock().changed.clone();
+        async move { changed.notified().await }
+    }
+
+    /// resolves once the state changed, e.g. for an async render loop like
+    /// `loop { render(); rt.changed().await; }`. Changes made after the previous future resolved
+    /// are remembered, so a change that happened during the render still counts, even though
+    /// nothing was waiting yet.
+    #[cfg(not(feature = "tokio"))]
+    pub fn changed(&self) -> impl Future<Output = ()> {
+        let subscribers = self.subscribers.clone();
+
+        std::future::poll_fn(move |cx| {
+            let mut subscribers = subscribers.lock();
+            if std::mem::take(&mut subscribers.changed) {
+                return Poll::Ready(());
+            }
+
+            if !subscribers.waiting.iter().any(|w| w.will_wake(cx.waker())) {
+                subscribers.waiting.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+    }
+
+    /// calls every subscriber and wakes the futures of changed. Must not be called while
+    /// holding the lock of the StateTree.
+    pub(crate) fn notify(&self) {
+        // subscribers may subscribe or unsubscribe themselves
+        #[cfg(not(feature = "tokio"))]
+        let (subscribers, waiting): (Vec<_>, _) = {
+            let mut subscribers = self.subscribers.lock();
+            subscribers.changed = true;
+
+            let list = subscribers.list.iter().map(|(_, f)| f.clone()).collect();
+            (list, std::mem::take(&mut subscribers.waiting))
+        };
+
+        #[cfg(not(feature = "tokio"))]
+        for waker in waiting {
+            waker.wake();
+        }
+
+        #[cfg(feature = "tokio")]
+        let (subscribers, changed): (Vec<_>, _) = {
+            let subscribers = self.subscribers.lock();
+            let list = subscribers.list.iter().map(|(_, f)| f.clone()).collect();
+            (list, subscribers.changed.clone())
+        };
+
+        #[cfg(feature = "tokio")]
+        changed.notify_one();
+
+        for f in subscribers {
+            f();
+        }
+    }
+
+    /// locks the StateTree. If some thread panicked while holding the lock, the poison gets
+    /// cleared instead of taking every following render down with it.
+    pub(crate) fn lock(&self) -> LockGuard<'_, StateTree> {
+        self.tree.lock()
+    }
+
+    /// like lock, but reports poisoning instead of recovering from it
+    pub(crate) fn try_lock(&self) -> Result<LockGuard<'_, StateTree>, HookError> {
+        self.tree.try_lock()
+    }
+
+    /// makes try_lock succeed again after a panic got caught while the lock was held
+    pub(crate) fn clear_poison(&self) {
+        self.tree.clear_poison();
+    }
+
+    /// runs `f`, collecting the writes of all setters it calls on this thread and applying them
+    /// at once when it returns, so no one can observe only some of them. Reads during `f` still
+    /// see the state from before the batch. If `f` panics, the collected writes get discarded.
+    pub fn batch<R>(&self, f: impl FnOnce() -> R) -> R {
+        if self.batching() {
+            // an enclosing batch will apply the writes
+            return f();
+        }
+
+        /// removes the batch again, even if `f` panics
+        struct Batch(RuntimeId);
+
+        impl Drop for Batch {
+            fn drop(&mut self) {
+                BATCHES.with(|batches| batches.borrow_mut().retain(|(batch, _)| *batch != self.0));
+            }
+        }
+
+        let id = self.id();
+        BATCHES.with(|batches| batches.borrow_mut().push((id, Vec::new())));
+        let batch = Batch(id);
+
+        let result = f();
+
+        let writes = BATCHES.with(|batches| {
+            let mut batches = batches.borrow_mut();
+            let (_, writes) = batches.iter_mut().find(|(batch, _)| *batch == id).unwrap();
+            std::mem::take(writes)
+        });
+        drop(batch);
+
+        self.commit(writes);
+        result
+    }
+
+    /// like batch, but for a future, e.g. an async render. The writes of setters called while it
+    /// gets polled are collected, and applied at once when it completes. If the future gets
+    /// dropped before that, they are discarded. The collected writes belong to the returned
+    /// future, so it is Send whenever `f` is, and can be spawned on a multi-threaded executor.
+    pub fn async_batch<F: Future>(&self, f: F) -> impl Future<Output = F::Output> {
+        /// collects the writes of a single poll, even if the poll panics
+        struct Polling<'a> {
+            id: RuntimeId,
+            writes: &'a mut Vec<Write>,
+        }
+
+        impl Drop for Polling<'_> {
+            fn drop(&mut self) {
+                BATCHES.with(|batches| {
+                    let mut batches = batches.borrow_mut();
+                    let position = batches
+                        .iter()
+                        .rposition(|(batch, _)| *batch == self.id)
+                        .unwrap();
+                    *self.writes = batches.remove(position).1;
+                });
+            }
+        }
+
+        let runtime = self.clone();
+        let mut future = Box::pin(f);
+        let mut writes = Vec::new();
+
+        std::future::poll_fn(move |cx| {
+            let id = runtime.id();
+            BATCHES.with(|batches| batches.borrow_mut().push((id, std::mem::take(&mut writes))));
+            let polling = Polling {
+                id,
+                writes: &mut writes,
+            };
+
+            let output = future.as_mut().poll(cx);
+            drop(polling);
+
+            if output.is_ready() {
+                runtime.commit(std::mem::take(&mut writes));
+            }
+            output
+        })
+    }
+
+    /// applies the writes collected by a batch in one go, and notifies subscribers
+    fn commit(&self, writes: Vec<Write>) {
+        if writes.is_empty() {
+            return;
+        }
+
+        let tree = self.lock();
+        let transitions: Vec<_> = writes
+            .into_iter()
+            .filter_map(|write| write(&tree))
+            .collect();
+        drop(tree);
+
+        self.transition(transitions);
+        self.notify();
+    }
+
+    /// applies `write` to the State at `cursor` and notifies subscribers, or defers it if this
+    /// runtime is batching. `index` is the register written to, which gets passed on to
+    /// middleware. Nothing happens if there's no State at `cursor` anymore, e.g. after reset.
+    pub(crate) fn write(
+        &self,
+        cursor: &[usize],
+        index: Option<usize>,
+        write: impl FnOnce(&State) + MaybeSend + 'static,
+    ) {
+        fn apply(
+            tree: &StateTree,
+            cursor: &[usize],
+            index: Option<usize>,
+            write: impl FnOnce(&State),
+        ) -> Option<Transition> {
+            let state = tree.try_get_state(cursor).ok()?;
+            write(state);
+            state.mark_dirty();
+            index.and_then(|index| Transition::of(state, cursor, index))
+        }
+
+        if !self.batching() {
+            let transition = apply(&self.lock(), cursor, index, write);
+            self.transition(transition);
+            self.notify();
+            return;
+        }
+
+        let id = self.id();
+        let cursor = cursor.to_vec();
+        BATCHES.with(|batches| {
+            let mut batches = batches.borrow_mut();
+            // the innermost batch, in case an async_batch gets polled during a batch
+            let (_, writes) = batches.iter_mut().rfind(|(batch, _)| *batch == id).unwrap();
+            writes.push(Box::new(move |tree| apply(tree, &cursor, index, write)));
+        });
+    }
+
+    /// whether writes to this runtime get deferred by a batch on this thread
+    pub(crate) fn batching(&self) -> bool {
+        let id = self.id();
+        BATCHES.with(|batches| batches.borrow().iter().any(|(batch, _)| *batch == id))
+    }
+
+    fn id(&self) -> RuntimeId {
+        Shared::as_ptr(&self.tree)
+    }
+}
+
+/// a Runtime that doesn't keep its state alive, for background threads that shouldn't outlive it
+#[cfg(not(feature = "thread-local"))]
+pub(crate) struct WeakRuntime {
+    tree: Weak<Lock<StateTree>>,
+    subscribers: Weak<Lock<Subscribers>>,
+    clock: Shared<dyn Clock>,
+    executor: Shared<dyn Executor>,
+    tasks: Shared<Lock<Vec<WeakShared<dyn Cancel>>>>,
+    max_hooks: usize,
+    #[cfg(feature = "history")]
+    history: Weak<Lock<History>>,
+}
+
+#[cfg(not(feature = "thread-local"))]
+impl WeakRuntime {
+    pub(crate) fn upgrade(&self) -> Option<Runtime> {
+        Some(Runtime {
+            tree: self.tree.upgrade()?,
+            subscribers: self.subscribers.upgrade()?,
+            clock: self.clock.clone(),
+            executor: self.executor.clone(),
+            tasks: self.tasks.clone(),
+            max_hooks: self.max_hooks,
+            #[cfg(feature = "history")]
+            history: self.history.upgrade()?,
+        })
+    }
+}
+
+/// throws away all state of the global runtime, see Runtime::reset
+pub fn reset_global() {
+    Runtime::global().reset();
+}
+
+/// runs `f` with a fresh runtime standing in for the global one on this thread, e.g. so tests
+/// using Hook::default() can run in parallel without sharing state. The previous global runtime
+/// is back once `f` returns or panics. Threads spawned by `f` still see the actual global
+/// runtime.
+pub fn with_isolated_state<R>(f: impl FnOnce() -> R) -> R {
+    /// uninstalls the runtime once dropped, even while unwinding
+    struct Isolated;
+
+    impl Drop for Isolated {
+        fn drop(&mut self) {
+            let runtime = ISOLATED.with(|isolated| isolated.borrow_mut().pop());
+            // dropping the state runs cleanups, which might use the global runtime themselves
+            drop(runtime);
+        }
+    }
+
+    ISOLATED.with(|isolated| isolated.borrow_mut().push(Runtime::new()));
+    let _isolated = Isolated;
+    f()
+}
+
+/// drops all state of the global runtime, see Runtime::shutdown. Call this before the program
+/// exits, so stored values get to close their sockets and the like.
+pub fn shutdown() {
+    Runtime::global().shutdown();
+}
+
+/// shows the type name of every register, see Runtime::debug to see their values
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        DebugRuntime {
+            runtime: self,
+            registry: None,
+        }
+        .fmt(f)
+    }
+}
+
+/// a runtime starting out with the given state, e.g. one hydrated from a snapshot
+impl From<StateTree> for Runtime {
+    fn from(tree: StateTree) -> Self {
+        Runtime {
+            tree: Shared::new(Lock::new(tree)),
+            ..Runtime::default()
+        }
+    }
+}
+
+/// seeds the state of a runtime before its first render, e.g. to drive components into a
+/// specific state in tests. Hooks recover seeded registers just like the ones of a previous
+/// render.
+pub struct RuntimeBuilder {
+    tree: StateTree,
+    cursor: Vec<usize>,
+    max_hooks: usize,
+}
+
+impl Default for RuntimeBuilder {
+    fn default() -> Self {
+        RuntimeBuilder {
+            tree: StateTree::default(),
+            cursor: Vec::new(),
+            max_hooks: DEFAULT_MAX_HOOKS,
+        }
+    }
+}
+
+impl RuntimeBuilder {
+    pub fn new() -> Self {
+        RuntimeBuilder::default()
+    }
+
+    /// see Runtime::max_hooks_per_node, defaults to 10000
+    pub fn max_hooks_per_node(mut self, limit: usize) -> Self {
+        self.max_hooks = limit;
+        self
+    }
+
+    /// makes the following calls to push seed the state at `cursor`, which is the path of slots
+    /// of children from the root. Children on the way get created, and are rendered by position.
+    pub fn at(mut self, cursor: &[usize]) -> Self {
+        self.tree.get_tree_or_insert(cursor);
+        self.cursor = cursor.to_vec();
+        self
+    }
+
+    /// appends a register holding `value`, for the next hook at the current cursor
+    pub fn push<T: 'static + MaybeSend>(self, value: T) -> Self {
+        self.tree
+            .get_state(&self.cursor)
+            .write()
+            .push(Register::new(value));
+        self
+    }
+
+    pub fn build(self) -> Runtime {
+        Runtime {
+            max_hooks: self.max_hooks,
+            ..Runtime::from(self.tree)
+        }
+    }
+
+    /// only the StateTree, e.g. to put it into a running Runtime with Runtime::replace_subtree
+    pub fn build_tree(self) -> StateTree {
+        self.tree
+    }
+}
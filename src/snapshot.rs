@@ -0,0 +1,540 @@
+//! snapshots of a whole StateTree, e.g. to render on a server and ship the state to the client.
+//! Registers only hold `dyn Any`, so every type stored in them has to be registered in a
+//! TypeRegistry first. Snapshots come as JSON with the `json` feature, and in a compact binary
+//! format with the `bincode` feature.
+
+// the `serde` feature on its own doesn't enable any format to use all of this
+#![cfg_attr(not(any(feature = "json", feature = "bincode")), allow(dead_code))]
+
+use crate::state::{AnyBox, Register, StateTree};
+use crate::{HookError, MaybeSend};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+#[cfg(all(test, any(feature = "json", feature = "bincode")))]
+mod tests {
+    use super::{HydrationError, TypeRegistry};
+    use crate::{Runtime, StateTree};
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_round_trip() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, set) = hook.use_state(vec![1u8, 2]);
+        set(vec![3]);
+        let (_, _) = hook.use_state_copy(-4i64);
+        let (_, set) = hook.with_keyed_child("item", |hook| hook.use_state(String::new()));
+        set("recovered".to_string());
+        hook.with_child(|hook| hook.use_state(0.5f32));
+        hook.unmount_child(0);
+
+        let mut registry = TypeRegistry::new();
+        registry
+            .register::<Vec<u8>>()
+            .register::<i64>()
+            .register::<String>()
+            .register::<f32>();
+        let bytes = rt.to_bincode(&registry).unwrap();
+
+        let rt = Runtime::from(StateTree::from_bincode(&bytes, &registry).unwrap());
+        let mut hook = rt.hook();
+        assert_eq!(hook.use_state(Vec::<u8>::new()).0, [3]);
+        assert_eq!(hook.use_state(0i64).0, -4);
+        let (item, _) = hook.with_keyed_child("item", |hook| hook.use_state(String::new()));
+        assert_eq!(item, "recovered");
+        // the slot of the unmounted child gets reused
+        hook.with_child(|hook| hook.use_state(0.5f32));
+        assert_eq!(rt.lock().children.len(), 2);
+
+        // a client that doesn't know about some type anymore
+        let mut registry = TypeRegistry::new();
+        registry.register::<Vec<u8>>().register::<i64>();
+        match StateTree::from_bincode(&bytes, &registry) {
+            Err(HydrationError::UnknownType {
+                path, type_name, ..
+            }) => {
+                assert_eq!(path, [0]);
+                assert_eq!(type_name, "alloc::string::String");
+            }
+            _ => panic!("expected hydration to fail"),
+        }
+
+        assert!(StateTree::from_bincode(&bytes[..bytes.len() / 2], &registry).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn restore_subtree() {
+        let rt = Runtime::new();
+        let render = || {
+            let mut hook = rt.hook();
+            let first = hook.with_child(|hook| hook.use_state(String::from("draft")));
+            let second = hook.with_child(|hook| hook.use_state(0u32));
+            (first, second)
+        };
+
+        render();
+        let mut registry = TypeRegistry::new();
+        registry.register::<String>();
+        let snapshot = rt.snapshot_subtree(&[0], &registry).unwrap();
+
+        let ((_, edit), (_, count)) = render();
+        edit("edited".to_string());
+        count(1);
+        assert_eq!(render().0 .0, "edited");
+
+        rt.restore_subtree(&[0], snapshot, &registry).unwrap();
+        let ((first, _), (second, _)) = render();
+        assert_eq!(first, "draft");
+        assert_eq!(second, 1);
+
+        use crate::HookError;
+        let snapshot = rt.snapshot_subtree(&[0], &registry).unwrap();
+        assert!(matches!(
+            rt.restore_subtree(&[2], snapshot, &registry),
+            Err(HydrationError::Hook(HookError::MissingNode(_)))
+        ));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn snapshot_missing_subtree() {
+        use super::SerializeError;
+        use crate::HookError;
+
+        let rt = Runtime::new();
+        rt.hook().with_child(|hook| hook.use_state(0));
+
+        let registry = TypeRegistry::new();
+        assert!(matches!(
+            rt.snapshot_subtree(&[0, 3], &registry),
+            Err(SerializeError::Hook(HookError::MissingNode(_)))
+        ));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn to_json() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(1i32);
+        let (_, _) = hook.use_state(String::from("root"));
+        let (_, _) = hook.with_child(|hook| hook.use_state(true));
+        let (_, _) = hook.with_keyed_child("item", |hook| hook.use_state(2.5f64));
+
+        let mut registry = TypeRegistry::new();
+        registry
+            .register::<i32>()
+            .register::<String>()
+            .register::<bool>()
+            .register::<f64>();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&rt.to_json(&registry).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "registers": [
+                    { "type": "i32", "value": 1 },
+                    { "type": "alloc::string::String", "value": "root" },
+                ],
+                "children": [
+                    {
+                        "registers": [{ "type": "bool", "value": true }],
+                        "children": [],
+                        "positional": [],
+                        "keys": {},
+                    },
+                    {
+                        "registers": [{ "type": "f64", "value": 2.5 }],
+                        "children": [],
+                        "positional": [],
+                        "keys": {},
+                    },
+                ],
+                "positional": [0],
+                "keys": { "item": 1 },
+            })
+        );
+
+        let mut registry = TypeRegistry::new();
+        registry.register::<i32>();
+        assert!(rt.to_json(&registry).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn round_trip() {
+        let rt = Runtime::new();
+        let mut hook = rt.hook();
+        let (_, set) = hook.use_state(vec![1u8, 2]);
+        set(vec![3]);
+        let (_, set) = hook.with_keyed_child("item", |hook| hook.use_state(String::new()));
+        set("hydrated".to_string());
+
+        let mut registry = TypeRegistry::new();
+        registry.register::<Vec<u8>>().register::<String>();
+        let json = rt.to_json(&registry).unwrap();
+
+        let rt = Runtime::from(StateTree::from_json(&json, &registry).unwrap());
+        let mut hook = rt.hook();
+        let (bytes, _) = hook.use_state(Vec::<u8>::new());
+        let (item, _) = hook.with_keyed_child("item", |hook| hook.use_state(String::new()));
+        assert_eq!(bytes, [3]);
+        assert_eq!(item, "hydrated");
+
+        // a client that doesn't know about some type anymore
+        let mut registry = TypeRegistry::new();
+        registry.register::<Vec<u8>>();
+        match StateTree::from_json(&json, &registry) {
+            Err(HydrationError::UnknownType {
+                path,
+                index,
+                type_name,
+            }) => {
+                assert_eq!(path, [0]);
+                assert_eq!(index, 0);
+                assert_eq!(type_name, "alloc::string::String");
+            }
+            _ => panic!("expected hydration to fail"),
+        }
+    }
+}
+
+/// knows how to serialize the types stored in registers
+#[derive(Default)]
+pub struct TypeRegistry {
+    types: HashMap<TypeId, Entry>,
+    names: HashMap<&'static str, TypeId>,
+}
+
+struct Entry {
+    name: &'static str,
+    #[cfg(feature = "json")]
+    to_json: fn(&dyn Any) -> serde_json::Result<serde_json::Value>,
+    #[cfg(feature = "json")]
+    from_json: fn(serde_json::Value) -> serde_json::Result<AnyBox>,
+    #[cfg(feature = "bincode")]
+    to_bincode: fn(&dyn Any) -> bincode::Result<Vec<u8>>,
+    #[cfg(feature = "bincode")]
+    from_bincode: fn(Vec<u8>) -> bincode::Result<AnyBox>,
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        TypeRegistry::default()
+    }
+
+    /// makes registers holding a `T` serializable. Types are identified by their type name, so
+    /// snapshots can only be restored by builds using the same names.
+    pub fn register<T>(&mut self) -> &mut Self
+    where
+        T: 'static + Serialize + DeserializeOwned + MaybeSend,
+    {
+        let entry = Entry {
+            name: std::any::type_name::<T>(),
+            #[cfg(feature = "json")]
+            to_json: |value| serde_json::to_value(value.downcast_ref::<T>().unwrap()),
+            #[cfg(feature = "json")]
+            from_json: |value| Ok(Box::new(serde_json::from_value::<T>(value)?)),
+            #[cfg(feature = "bincode")]
+            to_bincode: |value| bincode::serialize(value.downcast_ref::<T>().unwrap()),
+            #[cfg(feature = "bincode")]
+            from_bincode: |bytes| Ok(Box::new(bincode::deserialize::<T>(&bytes)?)),
+        };
+
+        self.names.insert(entry.name, TypeId::of::<T>());
+        self.types.insert(TypeId::of::<T>(), entry);
+        self
+    }
+
+    fn get(&self, value: &dyn Any) -> Option<&Entry> {
+        self.types.get(&value.type_id())
+    }
+
+    fn by_name(&self, name: &str) -> Option<&Entry> {
+        self.types.get(self.names.get(name)?)
+    }
+}
+
+#[derive(Debug)]
+pub enum SerializeError {
+    /// register `index` of the State at `path` holds a type missing from the TypeRegistry
+    UnregisteredType { path: Vec<usize>, index: usize },
+    /// there is nothing to take a snapshot of, e.g. as the cursor points nowhere
+    Hook(HookError),
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    #[cfg(feature = "bincode")]
+    Bincode(bincode::Error),
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::UnregisteredType { path, index } => write!(
+                f,
+                "state hook #{index} at {path:?} holds a type missing from the TypeRegistry"
+            ),
+            SerializeError::Hook(e) => write!(f, "failed to serialize state: {e}"),
+            #[cfg(feature = "json")]
+            SerializeError::Json(e) => write!(f, "failed to serialize state: {e}"),
+            #[cfg(feature = "bincode")]
+            SerializeError::Bincode(e) => write!(f, "failed to serialize state: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SerializeError {}
+
+impl From<HookError> for SerializeError {
+    fn from(e: HookError) -> Self {
+        SerializeError::Hook(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum HydrationError {
+    /// register `index` of the State at `path` holds a type missing from the TypeRegistry,
+    /// probably because the snapshot got taken by a different version of the app
+    UnknownType {
+        path: Vec<usize>,
+        index: usize,
+        type_name: String,
+    },
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    /// the bytes are no snapshot, or got cut off
+    #[cfg(feature = "bincode")]
+    Bincode(bincode::Error),
+    /// there is no subtree to restore into, e.g. as the cursor points nowhere
+    Hook(HookError),
+}
+
+impl fmt::Display for HydrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HydrationError::UnknownType {
+                path,
+                index,
+                type_name,
+            } => write!(
+                f,
+                "state hook #{index} at {path:?} holds {type_name}, which is missing from the TypeRegistry"
+            ),
+            #[cfg(feature = "json")]
+            HydrationError::Json(e) => write!(f, "failed to deserialize state: {e}"),
+            #[cfg(feature = "bincode")]
+            HydrationError::Bincode(e) => write!(f, "failed to deserialize state: {e}"),
+            HydrationError::Hook(e) => write!(f, "failed to restore state: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for HydrationError {}
+
+impl From<HookError> for HydrationError {
+    fn from(e: HookError) -> Self {
+        HydrationError::Hook(e)
+    }
+}
+
+/// a copy of a subtree taken by Runtime::snapshot_subtree, which Runtime::restore_subtree puts
+/// back in place
+#[cfg(feature = "json")]
+pub struct Snapshot {
+    tree: TreeSnapshot<serde_json::Value>,
+}
+
+#[cfg(feature = "json")]
+impl Snapshot {
+    /// copies the subtree at `cursor`, going through JSON as registers can't be cloned otherwise.
+    /// Fails with HookError::MissingNode if there is nothing at `cursor`.
+    pub(crate) fn take(
+        tree: &StateTree,
+        cursor: &[usize],
+        registry: &TypeRegistry,
+    ) -> Result<Snapshot, SerializeError> {
+        let subtree = tree.try_get_tree(cursor).map_err(HookError::from)?;
+
+        let tree = subtree.snapshot(registry, &mut cursor.to_vec(), &|entry, value| {
+            (entry.to_json)(value).map_err(SerializeError::Json)
+        })?;
+
+        Ok(Snapshot { tree })
+    }
+
+    /// rebuilds the subtree that got copied from `cursor`
+    pub(crate) fn restore(
+        self,
+        cursor: &[usize],
+        registry: &TypeRegistry,
+    ) -> Result<StateTree, HydrationError> {
+        StateTree::hydrate(
+            self.tree,
+            registry,
+            &mut cursor.to_vec(),
+            &|entry, value| (entry.from_json)(value).map_err(HydrationError::Json),
+        )
+    }
+}
+
+/// mirrors the shape of a StateTree, with every register converted to `V`
+#[derive(Serialize, Deserialize)]
+struct TreeSnapshot<V> {
+    registers: Vec<RegisterSnapshot<V>>,
+    children: Vec<TreeSnapshot<V>>,
+    positional: Vec<Option<usize>>,
+    keys: HashMap<String, usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RegisterSnapshot<V> {
+    #[serde(rename = "type")]
+    type_name: String,
+    value: V,
+}
+
+impl StateTree {
+    /// serializes the whole tree, including the state of all children
+    #[cfg(feature = "json")]
+    pub fn to_json(&self, registry: &TypeRegistry) -> Result<String, SerializeError> {
+        let snapshot = self.snapshot(registry, &mut Vec::new(), &|entry, value| {
+            (entry.to_json)(value).map_err(SerializeError::Json)
+        })?;
+
+        serde_json::to_string(&snapshot).map_err(SerializeError::Json)
+    }
+
+    /// restores a tree serialized by to_json
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str, registry: &TypeRegistry) -> Result<StateTree, HydrationError> {
+        let snapshot = serde_json::from_str(json).map_err(HydrationError::Json)?;
+
+        StateTree::hydrate(snapshot, registry, &mut Vec::new(), &|entry, value| {
+            (entry.from_json)(value).map_err(HydrationError::Json)
+        })
+    }
+
+    /// like to_json, but in a compact binary format. Every register gets tagged with the name of
+    /// its type, like in JSON, and its value is stored as bytes of their own, so a value that
+    /// can't be read doesn't throw off the rest.
+    #[cfg(feature = "bincode")]
+    pub fn to_bincode(&self, registry: &TypeRegistry) -> Result<Vec<u8>, SerializeError> {
+        let snapshot = self.snapshot(registry, &mut Vec::new(), &|entry, value| {
+            (entry.to_bincode)(value).map_err(SerializeError::Bincode)
+        })?;
+
+        bincode::serialize(&snapshot).map_err(SerializeError::Bincode)
+    }
+
+    /// restores a tree serialized by to_bincode. Registers tagged with a type missing from
+    /// `registry` fail with HydrationError::UnknownType.
+    #[cfg(feature = "bincode")]
+    pub fn from_bincode(
+        bytes: &[u8],
+        registry: &TypeRegistry,
+    ) -> Result<StateTree, HydrationError> {
+        let snapshot = bincode::deserialize(bytes).map_err(HydrationError::Bincode)?;
+
+        StateTree::hydrate(snapshot, registry, &mut Vec::new(), &|entry, value| {
+            (entry.from_bincode)(value).map_err(HydrationError::Bincode)
+        })
+    }
+
+    /// rebuilds a tree from a snapshot, converting every register back with `convert`
+    fn hydrate<V>(
+        snapshot: TreeSnapshot<V>,
+        registry: &TypeRegistry,
+        path: &mut Vec<usize>,
+        convert: &dyn Fn(&Entry, V) -> Result<AnyBox, HydrationError>,
+    ) -> Result<StateTree, HydrationError> {
+        let values = snapshot
+            .registers
+            .into_iter()
+            .enumerate()
+            .map(|(index, register)| {
+                let entry = registry.by_name(&register.type_name).ok_or_else(|| {
+                    HydrationError::UnknownType {
+                        path: path.clone(),
+                        index,
+                        type_name: register.type_name.clone(),
+                    }
+                })?;
+
+                Ok((convert(entry, register.value)?, entry.name))
+            })
+            .collect::<Result<Vec<_>, HydrationError>>()?;
+
+        let mut children = Vec::with_capacity(snapshot.children.len());
+        for (slot, child) in snapshot.children.into_iter().enumerate() {
+            path.push(slot);
+            children.push(StateTree::hydrate(child, registry, path, convert)?);
+            path.pop();
+        }
+
+        let mut tree = StateTree::default();
+        for (index, (value, type_name)) in values.into_iter().enumerate() {
+            // deserialized values get installed like any other raw value, into a placeholder
+            tree.state.write().push(Register::copy(()));
+            tree.state.set_raw(index, value, type_name)?;
+        }
+        tree.children = children;
+        tree.positional = snapshot.positional;
+        tree.keys = snapshot.keys;
+        // slots neither rendered by position nor by key belong to unmounted children
+        tree.free = (0..tree.children.len())
+            .filter(|slot| {
+                !tree.positional.contains(&Some(*slot)) && !tree.keys.values().any(|k| k == slot)
+            })
+            .collect();
+        Ok(tree)
+    }
+
+    /// converts every register to a `V` using the registry entry of its type
+    fn snapshot<V>(
+        &self,
+        registry: &TypeRegistry,
+        path: &mut Vec<usize>,
+        convert: &dyn Fn(&Entry, &dyn Any) -> Result<V, SerializeError>,
+    ) -> Result<TreeSnapshot<V>, SerializeError> {
+        let registers = self.state.read();
+        let registers = registers
+            .in_order()
+            .into_iter()
+            .map(|(index, register)| {
+                let value = register.as_any();
+                let entry =
+                    registry
+                        .get(value)
+                        .ok_or_else(|| SerializeError::UnregisteredType {
+                            path: path.clone(),
+                            index,
+                        })?;
+
+                Ok(RegisterSnapshot {
+                    type_name: entry.name.to_string(),
+                    value: convert(entry, value)?,
+                })
+            })
+            .collect::<Result<Vec<_>, SerializeError>>()?;
+
+        let mut children = Vec::with_capacity(self.children.len());
+        for (slot, child) in self.children.iter().enumerate() {
+            path.push(slot);
+            children.push(child.snapshot(registry, path, convert)?);
+            path.pop();
+        }
+
+        Ok(TreeSnapshot {
+            registers,
+            children,
+            positional: self.positional.clone(),
+            keys: self.keys.clone(),
+        })
+    }
+}
@@ -0,0 +1,860 @@
+use crate::sync::{MaybeSend, ReadGuard, RwCell, Shared, WriteGuard};
+use crate::{HookError, TreeError};
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::mem::{align_of, size_of, MaybeUninit};
+use std::panic::Location;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+#[cfg(test)]
+mod tests {
+    use super::StateTree;
+    use crate::TreeError;
+
+    #[test]
+    fn try_get_state() {
+        let mut tree = StateTree::default();
+        tree.children.push(StateTree::default());
+        tree.children[0].children.push(StateTree::default());
+
+        assert!(tree.try_get_state(&[0, 0]).is_ok());
+        assert_eq!(
+            tree.try_get_state(&[0, 1]).err(),
+            Some(TreeError {
+                depth: 1,
+                index: 1,
+                len: 1
+            })
+        );
+        assert!(tree.try_get_state(&[3]).is_err());
+    }
+
+    #[test]
+    fn set_raw() {
+        use crate::HookError;
+
+        let rt = crate::Runtime::new();
+        let (_, _) = rt.hook().use_state(1u32);
+
+        let previous = rt.lock().state.set_raw(0, Box::new(7u32), "u32").unwrap();
+        drop(previous);
+        assert_eq!(rt.hook().use_state(0u32).0, 7);
+
+        let tree = rt.lock();
+        assert_eq!(
+            tree.state.set_raw(1, Box::new(8u32), "u32").err(),
+            Some(HookError::OutOfBounds { index: 1, len: 1 })
+        );
+
+        // a box of the wrong type only fails once it gets read
+        drop(tree.state.set_raw(0, Box::new("text"), "&str"));
+        assert_eq!(
+            tree.state.read_state(0, |value: &u32| *value).err(),
+            Some(HookError::TypeMismatch {
+                index: 0,
+                expected: "u32",
+                found: Some("&str")
+            })
+        );
+    }
+
+    #[test]
+    fn inline_primitives() {
+        use super::Register;
+        use std::fmt::Debug;
+
+        /// round-trips `value` through an inline register, then reads it as an `Other`
+        fn round_trip<T, Other>(value: T)
+        where
+            T: 'static + Copy + Debug + PartialEq + Send,
+            Other: 'static,
+        {
+            let register = Register::copy(value);
+            assert!(register.is_inline(), "{} isn't inline", register.type_name);
+            assert_eq!(register.get::<T>(0), Ok(&value));
+            assert!(register.get::<Other>(0).is_err());
+        }
+
+        round_trip::<_, i8>(7u8);
+        round_trip::<_, u8>(-7i8);
+        round_trip::<_, i16>(700u16);
+        round_trip::<_, u16>(-700i16);
+        round_trip::<_, f32>(70_000u32);
+        round_trip::<_, f32>(-70_000i32);
+        round_trip::<_, i32>(1.5f32);
+        round_trip::<_, f64>(u64::MAX);
+        round_trip::<_, u64>(i64::MIN);
+        round_trip::<_, u64>(2.5f64);
+        round_trip::<_, i128>(u128::MAX);
+        round_trip::<_, u128>(i128::MIN);
+        round_trip::<_, u32>('x');
+        round_trip::<_, u8>(true);
+    }
+
+    #[test]
+    fn type_name_at() {
+        let rt = crate::Runtime::new();
+        let mut hook = rt.hook();
+        let (_, _) = hook.use_state(String::new());
+        let (_, _) = hook.use_state_copy(1u8);
+        let (_, _) = hook.use_state(vec![true]);
+
+        let tree = rt.lock();
+        let names: Vec<_> = (0..4).map(|index| tree.state.type_name_at(index)).collect();
+        assert_eq!(
+            names,
+            [
+                Some("alloc::string::String"),
+                Some("u8"),
+                Some("alloc::vec::Vec<bool>"),
+                None
+            ]
+        );
+        drop(tree);
+        assert_eq!(rt.type_name_at(&[], 1), Some("u8"));
+        assert_eq!(rt.type_name_at(&[0], 0), None);
+    }
+
+    #[test]
+    fn rerender_from_start() {
+        let rt = crate::Runtime::new();
+        let render = || {
+            let mut hook = rt.hook();
+            let (value, set) = hook.use_state(1);
+            set(value * 2);
+            let (nested, _) =
+                hook.with_child(|hook| hook.with_child(|hook| hook.use_state("deep")));
+            (value, nested)
+        };
+
+        assert_eq!(render(), (1, "deep"));
+        assert_eq!(render(), (2, "deep"));
+        assert_eq!(render(), (4, "deep"));
+        assert_eq!(rt.lock().stats().registers, 2);
+    }
+}
+
+/// the state of a component and all of its children. Every Runtime owns one. It keeps no read
+/// position: each render pass counts the hooks and children it used in its own Hook, see Claims,
+/// so the next pass starts from the beginning without anything to reset.
+#[derive(Default)]
+pub struct StateTree {
+    pub(crate) state: State,
+
+    /// When some component uses state, it's subcomponents will get their own state, which can be
+    /// found here
+    pub(crate) children: Vec<StateTree>,
+
+    /// index into children for every child rendered by position, in render order. None once the
+    /// child got unmounted, until it gets rendered again.
+    pub(crate) positional: Vec<Option<usize>>,
+
+    /// index into children for every child rendered by key
+    pub(crate) keys: HashMap<String, usize>,
+
+    /// slots of children that got unmounted, which get reused by the next child that gets mounted
+    pub(crate) free: Vec<usize>,
+
+    /// which children got rendered since the last call to Hook::prune_unvisited
+    pub(crate) visited: Vec<bool>,
+
+    /// number of hooks used on the first render of this component, see Hook::end_render
+    pub(crate) hook_count: Option<usize>,
+
+    /// values provided to this component and all of its children with Hook::provide_context, by
+    /// their type and the name of their ContextKey, if they got provided with one
+    pub(crate) contexts: HashMap<(TypeId, Option<&'static str>), AnyBox>,
+}
+
+/// children get dropped before their parents, so their cleanups may still rely on the state
+/// of their parents
+impl Drop for StateTree {
+    fn drop(&mut self) {
+        self.children.clear();
+    }
+}
+
+impl StateTree {
+    pub(crate) fn get_state(&self, cursor: &[usize]) -> &State {
+        self.try_get_state(cursor)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// like get_state, but returns an error if the cursor points to a child that doesn't exist
+    pub(crate) fn try_get_state(&self, cursor: &[usize]) -> Result<&State, TreeError> {
+        Ok(&self.try_get_tree(cursor)?.state)
+    }
+
+    /// the subtree the cursor points to
+    pub(crate) fn try_get_tree(&self, cursor: &[usize]) -> Result<&StateTree, TreeError> {
+        let mut node = self;
+        for (depth, &index) in cursor.iter().enumerate() {
+            node = node.children.get(index).ok_or(TreeError {
+                depth,
+                index,
+                len: node.children.len(),
+            })?;
+        }
+
+        Ok(node)
+    }
+
+    /// like try_get_tree, but mutable
+    pub(crate) fn try_get_tree_mut(
+        &mut self,
+        cursor: &[usize],
+    ) -> Result<&mut StateTree, TreeError> {
+        let mut node = self;
+        for (depth, &index) in cursor.iter().enumerate() {
+            let len = node.children.len();
+            node = node
+                .children
+                .get_mut(index)
+                .ok_or(TreeError { depth, index, len })?;
+        }
+
+        Ok(node)
+    }
+
+    /// like get_tree_mut, but children on the way that don't exist yet get created, as children
+    /// rendered by position
+    pub(crate) fn get_tree_or_insert(&mut self, cursor: &[usize]) -> &mut StateTree {
+        let mut node = self;
+        for &slot in cursor {
+            while node.children.len() <= slot {
+                node.positional.push(Some(node.children.len()));
+                node.children.push(StateTree::default());
+            }
+            node = &mut node.children[slot];
+        }
+
+        node
+    }
+
+    pub(crate) fn get_tree_mut(&mut self, cursor: &[usize]) -> &mut StateTree {
+        if cursor.is_empty() {
+            return self;
+        }
+
+        self.children[cursor[0]].get_tree_mut(&cursor[1..])
+    }
+
+    /// whether any State in this tree got written to since the last call, clearing all of them
+    pub(crate) fn take_dirty(&self) -> bool {
+        self.children
+            .iter()
+            .fold(self.state.take_dirty(), |dirty, child| {
+                child.take_dirty() | dirty
+            })
+    }
+
+    /// slot for a child that gets rendered for the first time. Slots of unmounted children get
+    /// reused, so they stay compact when children come and go.
+    pub(crate) fn mount(&mut self) -> usize {
+        // unmounting took the state out of the slot, so the child starts out fresh
+        if let Some(slot) = self.free.pop() {
+            return slot;
+        }
+
+        self.children.push(StateTree::default());
+        self.children.len() - 1
+    }
+
+    /// frees the slot of a child for reuse, returning its state so it can be dropped once the
+    /// StateTree is unlocked
+    pub(crate) fn unmount(&mut self, slot: usize) -> StateTree {
+        self.free.push(slot);
+        std::mem::take(&mut self.children[slot])
+    }
+
+    pub(crate) fn visit(&mut self, slot: usize) {
+        if self.visited.len() <= slot {
+            self.visited.resize(slot + 1, false);
+        }
+
+        self.visited[slot] = true;
+    }
+}
+
+#[cfg(not(feature = "thread-local"))]
+pub(crate) type AnyBox = Box<dyn Any + Send>;
+
+/// registers never leave their thread with the `thread-local` feature, so they don't need to be
+/// Send
+#[cfg(feature = "thread-local")]
+pub(crate) type AnyBoxLocal = Box<dyn Any>;
+
+#[cfg(feature = "thread-local")]
+pub(crate) type AnyBox = AnyBoxLocal;
+
+/// a value stored by some hook
+pub(crate) struct Register {
+    value: Value,
+    /// name of the type of `value`, so type mismatches can tell what they found
+    pub(crate) type_name: &'static str,
+    /// number of writes through setters, see Setter::last_seq
+    pub(crate) seq: u64,
+}
+
+enum Value {
+    Boxed(AnyBox),
+    /// a small Copy value, see Register::copy
+    Inline {
+        bytes: Inline,
+        /// views the bytes as the type they got written as. Only ever paired with bytes holding
+        /// that type.
+        as_any: unsafe fn(&Inline) -> &dyn Any,
+        as_any_mut: unsafe fn(&mut Inline) -> &mut dyn Any,
+    },
+}
+
+/// room for a small Copy value, aligned for any of them
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct Inline(MaybeUninit<[u8; 16]>);
+
+// every primitive, up to u128 and i128, fits inline
+const _: () =
+    assert!(size_of::<Inline>() >= size_of::<u128>() && align_of::<Inline>() >= align_of::<u128>());
+
+/// # Safety
+/// `bytes` must hold a `T`
+unsafe fn inline_any<T: 'static>(bytes: &Inline) -> &dyn Any {
+    &*bytes.0.as_ptr().cast::<T>()
+}
+
+/// # Safety
+/// `bytes` must hold a `T`
+unsafe fn inline_any_mut<T: 'static>(bytes: &mut Inline) -> &mut dyn Any {
+    &mut *bytes.0.as_mut_ptr().cast::<T>()
+}
+
+impl Register {
+    /// writes `register` over this one, counting it as another write of the same register.
+    /// Returns the previous one.
+    pub(crate) fn overwrite(&mut self, register: Register) -> Register {
+        let seq = self.seq + 1;
+        let previous = std::mem::replace(self, register);
+        self.seq = seq;
+        previous
+    }
+
+    /// exchanges the value of this register with the one of `other`, counting it as another
+    /// write of this register. The seq of `other` stays untouched, so this one never goes back.
+    #[cfg(feature = "history")]
+    pub(crate) fn swap_value(&mut self, other: &mut Register) {
+        std::mem::swap(&mut self.value, &mut other.value);
+        std::mem::swap(&mut self.type_name, &mut other.type_name);
+        self.seq += 1;
+    }
+
+    pub(crate) fn new<T>(value: T) -> Self
+    where
+        T: 'static + MaybeSend,
+    {
+        Register::boxed(Box::new(value), std::any::type_name::<T>())
+    }
+
+    pub(crate) fn boxed(value: AnyBox, type_name: &'static str) -> Self {
+        Register {
+            value: Value::Boxed(value),
+            type_name,
+            seq: 0,
+        }
+    }
+
+    /// like new, but stores `value` without allocating if it fits. Being Copy, it doesn't need
+    /// to be dropped.
+    pub(crate) fn copy<T>(value: T) -> Self
+    where
+        T: 'static + Copy + MaybeSend,
+    {
+        if size_of::<T>() > size_of::<Inline>() || align_of::<T>() > align_of::<Inline>() {
+            return Register::new(value);
+        }
+
+        let mut bytes = Inline(MaybeUninit::uninit());
+        // SAFETY: T fits into the bytes, and they are aligned for it
+        unsafe { bytes.0.as_mut_ptr().cast::<T>().write(value) };
+
+        Register {
+            value: Value::Inline {
+                bytes,
+                as_any: inline_any::<T>,
+                as_any_mut: inline_any_mut::<T>,
+            },
+            type_name: std::any::type_name::<T>(),
+            seq: 0,
+        }
+    }
+
+    pub(crate) fn as_any(&self) -> &dyn Any {
+        match &self.value {
+            Value::Boxed(value) => &**value,
+            // SAFETY: as_any belongs to the type the bytes got written as
+            Value::Inline { bytes, as_any, .. } => unsafe { as_any(bytes) },
+        }
+    }
+
+    pub(crate) fn as_any_mut(&mut self) -> &mut dyn Any {
+        match &mut self.value {
+            Value::Boxed(value) => &mut **value,
+            // SAFETY: as_any_mut belongs to the type the bytes got written as
+            Value::Inline {
+                bytes, as_any_mut, ..
+            } => unsafe { as_any_mut(bytes) },
+        }
+    }
+
+    /// whether the value is stored without a heap allocation
+    #[cfg(test)]
+    pub(crate) fn is_inline(&self) -> bool {
+        matches!(self.value, Value::Inline { .. })
+    }
+
+    pub(crate) fn downcast_ref<T: 'static>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    pub(crate) fn downcast_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.as_any_mut().downcast_mut()
+    }
+
+    /// like downcast_ref, but reports what was found instead. `index` is the index of the register.
+    pub(crate) fn get<T: 'static>(&self, index: usize) -> Result<&T, HookError> {
+        let type_name = self.type_name;
+        self.downcast_ref().ok_or(HookError::TypeMismatch {
+            index,
+            expected: std::any::type_name::<T>(),
+            found: Some(type_name),
+        })
+    }
+
+    /// mutable version of get
+    pub(crate) fn get_mut<T: 'static>(&mut self, index: usize) -> Result<&mut T, HookError> {
+        let type_name = self.type_name;
+        self.downcast_mut().ok_or(HookError::TypeMismatch {
+            index,
+            expected: std::any::type_name::<T>(),
+            found: Some(type_name),
+        })
+    }
+}
+
+/// tells States apart, every State gets a new one. A State that got replaced by a fresh one, e.g.
+/// by Runtime::reset, has a different generation than before, even at the same cursor. The same
+/// goes for registers taking over the slot of a dropped one, see Registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Generation(u64);
+
+impl Default for Generation {
+    fn default() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Generation(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// identifies a hook across render passes: where it got called from, and how many hooks got
+/// called from there before it during the same pass, which tells hooks called in a loop apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct HookId {
+    site: &'static Location<'static>,
+    occurrence: usize,
+}
+
+/// what a render pass of one component claimed of its registers so far, see Registers::claim.
+/// Every Hook keeps its own, so a fresh Hook matches registers from the start again.
+#[derive(Default)]
+pub(crate) struct Claims {
+    /// number of hooks called so far
+    pub(crate) count: usize,
+    /// number of hooks called from every call site so far
+    sites: HashMap<&'static Location<'static>, usize>,
+    /// indices of the registers claimed so far
+    claimed: HashSet<usize>,
+    /// whether some hook found its register by its HookId
+    matched: bool,
+    /// set for the render of a component, see Hook::begin_render. A hook it didn't call before
+    /// then gets a fresh register instead of adopting one another hook owns by position.
+    pub(crate) by_id: bool,
+}
+
+impl Claims {
+    /// the id of the hook called next, from `site`
+    pub(crate) fn next(&mut self, site: &'static Location<'static>) -> HookId {
+        let occurrence = self.sites.entry(site).or_default();
+        let id = HookId {
+            site,
+            occurrence: *occurrence,
+        };
+        *occurrence += 1;
+        self.count += 1;
+        id
+    }
+}
+
+/// the registers of a State, in a generational arena. Hooks find their register by their HookId
+/// instead of the order they get called in, so a hook that gets skipped on some render doesn't
+/// shift the registers of the hooks after it. Registers no hook knows by id yet, e.g. seeded or
+/// hydrated ones, get matched by position instead, see claim.
+#[derive(Default)]
+pub(crate) struct Registers {
+    slots: Vec<Slot>,
+    /// slots without a register, which get reused first
+    free: Vec<usize>,
+    /// the slot of every hook that claimed one, along with its generation back then
+    ids: HashMap<HookId, (usize, Generation)>,
+    /// number of slots holding a register
+    len: usize,
+}
+
+/// a register behind a lock of its own, so Hook::read_guard can hold on to it without keeping
+/// the whole StateTree locked
+pub(crate) struct RegisterCell(RwCell<Register>);
+
+// SAFETY: registers only need to be Send. Everything but Hook::read_guard only accesses them while
+// holding the lock of the StateTree, one thread at a time. The values read_guard hands out to
+// other threads meanwhile are Sync.
+#[cfg(not(feature = "thread-local"))]
+unsafe impl Sync for RegisterCell {}
+
+impl RegisterCell {
+    pub(crate) fn read(&self) -> ReadGuard<'_, Register> {
+        self.0.read()
+    }
+
+    fn write(&self) -> WriteGuard<'_, Register> {
+        self.0.write()
+    }
+}
+
+/// a register that may still be held on to by Hook::read_guard, see Registers::retain_claimed
+pub(crate) type SharedRegister = Shared<RegisterCell>;
+
+#[derive(Default)]
+struct Slot {
+    register: Option<SharedRegister>,
+    /// changes whenever a new register gets stored in the slot, so hooks and setters of the
+    /// previous one can tell it got dropped
+    generation: Generation,
+    /// how many hooks got called before the one that claimed the register last, which decides
+    /// the order registers get matched by position in, and get serialized in
+    position: usize,
+    /// whether some hook claimed the register by its HookId, after which no other hook may adopt
+    /// it by position
+    owned: bool,
+}
+
+impl Registers {
+    /// number of registers
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// locks register `index` for reading, next to any Hook::read_guard holding it
+    pub(crate) fn get(&self, index: usize) -> Option<ReadGuard<'_, Register>> {
+        Some(self.slots.get(index)?.register.as_ref()?.read())
+    }
+
+    /// locks register `index` for writing, waiting for every Hook::read_guard holding it
+    pub(crate) fn get_mut(&mut self, index: usize) -> Option<WriteGuard<'_, Register>> {
+        Some(self.slots.get(index)?.register.as_ref()?.write())
+    }
+
+    /// like get_mut, but None if register `index` isn't the one of `generation` anymore
+    pub(crate) fn current_mut(
+        &mut self,
+        index: usize,
+        generation: Generation,
+    ) -> Option<WriteGuard<'_, Register>> {
+        let slot = self.slots.get(index)?;
+        if slot.generation != generation {
+            return None;
+        }
+
+        Some(slot.register.as_ref()?.write())
+    }
+
+    /// register `index` itself, so it can be read from after the StateTree got unlocked, see
+    /// Hook::read_guard
+    pub(crate) fn shared(&self, index: usize) -> Option<SharedRegister> {
+        self.slots.get(index)?.register.clone()
+    }
+
+    /// register `index` without locking it, for a StateTree that isn't shared by a Runtime, so
+    /// nothing can hold on to its registers. None if there is no such register, or it's still
+    /// held on to after all.
+    #[cfg(feature = "local")]
+    pub(crate) fn get_exclusive(&mut self, index: usize) -> Option<&mut Register> {
+        let register = self.slots.get_mut(index)?.register.as_mut()?;
+        Some(Shared::get_mut(register)?.0.get_mut())
+    }
+
+    /// the generation of register `index`, see Slot::generation
+    pub(crate) fn generation(&self, index: usize) -> Option<Generation> {
+        let slot = self.slots.get(index)?;
+        slot.register.as_ref().map(|_| slot.generation)
+    }
+
+    /// every register along with its index, each locked for reading
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (usize, ReadGuard<'_, Register>)> {
+        self.indices()
+            .map(|index| (index, self.slots[index].register.as_ref().unwrap().read()))
+    }
+
+    /// like iter, but in the order their hooks got called in when they got claimed last
+    #[cfg_attr(not(any(feature = "json", feature = "bincode")), allow(dead_code))]
+    pub(crate) fn in_order(&self) -> Vec<(usize, ReadGuard<'_, Register>)> {
+        let mut registers: Vec<_> = self.iter().collect();
+        registers.sort_by_key(|(index, _)| self.slots[*index].position);
+        registers
+    }
+
+    /// whether there is a register `index`, without locking it
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        self.slots
+            .get(index)
+            .is_some_and(|slot| slot.register.is_some())
+    }
+
+    /// indices of the slots holding a register
+    fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.slots.len()).filter(|&index| self.contains(index))
+    }
+
+    /// the register of hook `id`, claiming it for this render pass. Falls back to the first
+    /// unclaimed register by position if `id` is new and no hook of this pass found its register by
+    /// id yet, so registers seeded by RuntimeBuilder, hydrated from a snapshot, or created through
+    /// another Hook get adopted by the hooks rendering them. A hook that is new to a component
+    /// whose other hooks are known gets a fresh register instead. None if the hook doesn't have a
+    /// register yet, or its register got dropped since.
+    pub(crate) fn find(&mut self, claims: &mut Claims, id: HookId) -> Option<usize> {
+        let position = claims.count - 1;
+
+        if let Some(&(index, generation)) = self.ids.get(&id) {
+            let live = self.generation(index) == Some(generation);
+            if !live || claims.claimed.contains(&index) {
+                return None;
+            }
+
+            claims.matched = true;
+            claims.claimed.insert(index);
+            self.slots[index].position = position;
+            return Some(index);
+        }
+
+        if claims.matched || claims.claimed.len() == self.len {
+            return None;
+        }
+
+        // while rendering a component, only registers no hook owns yet, e.g. seeded or hydrated
+        // ones, can be adopted, so a hook skipped on earlier renders gets a fresh register
+        // instead of the one of a hook after it
+        let index = self
+            .indices()
+            .filter(|&index| !(claims.by_id && self.slots[index].owned))
+            .filter(|index| !claims.claimed.contains(index))
+            .min_by_key(|&index| self.slots[index].position)?;
+
+        let slot = &mut self.slots[index];
+        slot.position = position;
+        slot.owned = true;
+        self.ids.insert(id, (index, slot.generation));
+        claims.claimed.insert(index);
+        Some(index)
+    }
+
+    /// stores `register` for hook `id`, which didn't find one, returning its index
+    pub(crate) fn insert(&mut self, claims: &mut Claims, id: HookId, register: Register) -> usize {
+        let position = claims.count - 1;
+        let index = self.push_at(position, register);
+        self.slots[index].owned = true;
+
+        self.ids.insert(id, (index, self.slots[index].generation));
+        claims.claimed.insert(index);
+        index
+    }
+
+    /// find, inserting the register made by `init` if there is none. Also returns whether it
+    /// got created.
+    pub(crate) fn claim(
+        &mut self,
+        claims: &mut Claims,
+        id: HookId,
+        init: impl FnOnce() -> Register,
+    ) -> (usize, bool) {
+        match self.find(claims, id) {
+            Some(index) => (index, false),
+            None => (self.insert(claims, id, init()), true),
+        }
+    }
+
+    /// stores `register` without any hook claiming it, positioned after all others, so the next
+    /// hook without a register of its own adopts it
+    pub(crate) fn push(&mut self, register: Register) -> usize {
+        let position = self.slots.iter().map(|slot| slot.position + 1).max();
+        self.push_at(position.unwrap_or(0), register)
+    }
+
+    fn push_at(&mut self, position: usize, register: Register) -> usize {
+        let slot = Slot {
+            register: Some(Shared::new(RegisterCell(RwCell::new(register)))),
+            generation: Generation::default(),
+            position,
+            owned: false,
+        };
+
+        self.len += 1;
+        match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = slot;
+                index
+            }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    /// drops the registers that didn't get claimed during the render pass, returning them so
+    /// they can be dropped once the StateTree is unlocked
+    pub(crate) fn retain_claimed(&mut self, claims: &Claims) -> Vec<SharedRegister> {
+        let mut unclaimed = Vec::new();
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.register.is_some() && !claims.claimed.contains(&index) {
+                unclaimed.extend(slot.register.take());
+                self.free.push(index);
+            }
+        }
+
+        // every register left is owned by some hook now, so the hooks of the dropped ones get a
+        // fresh register once they get called again
+        let slots = &self.slots;
+        self.ids.retain(|_, &mut (index, generation)| {
+            slots[index].register.is_some() && slots[index].generation == generation
+        });
+        self.len -= unclaimed.len();
+        unclaimed
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct State {
+    /// each state holds multible state registers, one for every hook
+    pub(crate) registers: RwCell<Registers>,
+    /// registers of use_state_named, which are kept apart so keys can't collide with indices
+    pub(crate) named: RwCell<HashMap<String, Register>>,
+    /// registers of use_state_keyed, by the type of their key and its hash
+    pub(crate) keyed: RwCell<HashMap<(TypeId, u64), Register>>,
+    /// whether some register got written to since the last call to Runtime::take_dirty. Only
+    /// accessed while the StateTree is locked, so the ordering doesn't matter.
+    dirty: AtomicBool,
+    /// see Setter::try_set
+    pub(crate) generation: Generation,
+}
+
+impl State {
+    pub(crate) fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// replaces the value of register `index` with `value` without looking at its type, e.g. to
+    /// install a value deserialized from a snapshot. `type_name` is what type mismatches report
+    /// as found. If `value` isn't of the type the hook using the register asks for, its next read
+    /// fails with HookError::TypeMismatch, which most hooks turn into a panic. Returns the
+    /// previous register, so it can be dropped once the StateTree is unlocked.
+    #[cfg_attr(not(any(feature = "raw", feature = "serde")), allow(dead_code))]
+    pub(crate) fn set_raw(
+        &self,
+        index: usize,
+        value: AnyBox,
+        type_name: &'static str,
+    ) -> Result<Register, HookError> {
+        let mut registers = self.write();
+        let len = registers.len();
+        let mut register = registers
+            .get_mut(index)
+            .ok_or(HookError::OutOfBounds { index, len })?;
+
+        self.mark_dirty();
+        Ok(register.overwrite(Register::boxed(value, type_name)))
+    }
+
+    fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::Relaxed)
+    }
+
+    pub(crate) fn read(&self) -> ReadGuard<'_, Registers> {
+        self.registers.read()
+    }
+
+    pub(crate) fn write(&self) -> WriteGuard<'_, Registers> {
+        self.registers.write()
+    }
+
+    /// name of the type stored in register `index`, None if there is no such register
+    pub(crate) fn type_name_at(&self, index: usize) -> Option<&'static str> {
+        Some(self.read().get(index)?.type_name)
+    }
+
+    /// passes the value of register `index` to `f` while holding the read lock, instead of
+    /// cloning it out
+    pub(crate) fn read_state<T: 'static, R>(
+        &self,
+        index: usize,
+        f: impl FnOnce(&T) -> R,
+    ) -> Result<R, HookError> {
+        let registers = self.read();
+        let len = registers.len();
+        let register = registers
+            .get(index)
+            .ok_or(HookError::OutOfBounds { index, len })?;
+
+        Ok(f(register.get::<T>(index)?))
+    }
+
+    /// claims the register of hook `id`, storing `value` in it if there is none yet, and returns
+    /// its index along with a clone of its value
+    pub(crate) fn use_state<T>(&self, claims: &mut Claims, id: HookId, value: T) -> (usize, T)
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        self.use_state_with(claims, id, || value)
+    }
+
+    /// same as use_state, but `init` only gets called if the register doesn't exist yet
+    pub(crate) fn use_state_with<T>(
+        &self,
+        claims: &mut Claims,
+        id: HookId,
+        init: impl FnOnce() -> T,
+    ) -> (usize, T)
+    where
+        T: 'static + Clone + MaybeSend,
+    {
+        let (index, value, _) = self
+            .try_use_register(claims, id, || Register::new(init()))
+            .unwrap_or_else(|e| panic!("{}", e));
+        (index, value)
+    }
+
+    /// like use_state_with, but `init` decides how the value gets stored, and a register of
+    /// another type is reported instead of panicking. Also returns whether the register just got
+    /// created.
+    pub(crate) fn try_use_register<T>(
+        &self,
+        claims: &mut Claims,
+        id: HookId,
+        init: impl FnOnce() -> Register,
+    ) -> Result<(usize, T, bool), HookError>
+    where
+        T: 'static + Clone,
+    {
+        let mut registers = self.write();
+        let (index, created) = registers.claim(claims, id, init);
+        let register = registers.get(index).expect("the register just got claimed");
+
+        Ok((index, register.get::<T>(index)?.clone(), created))
+    }
+}
@@ -0,0 +1,199 @@
+//! the primitives state gets shared and locked with. By default state can be shared between
+//! threads, with the `thread-local` feature every thread gets its own state instead, which doesn't
+//! need any synchronization.
+
+#[cfg(not(feature = "thread-local"))]
+pub(crate) use std::sync::{Arc as Shared, Weak as WeakShared};
+
+#[cfg(feature = "thread-local")]
+pub(crate) use std::rc::{Rc as Shared, Weak as WeakShared};
+
+/// state values need to be Send, so state can be shared between threads. With the `thread-local`
+/// feature they never leave their thread, so this gets implemented for every type.
+#[cfg(not(feature = "thread-local"))]
+pub trait MaybeSend: Send {}
+
+#[cfg(not(feature = "thread-local"))]
+impl<T: ?Sized + Send> MaybeSend for T {}
+
+#[cfg(feature = "thread-local")]
+pub trait MaybeSend {}
+
+#[cfg(feature = "thread-local")]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// like MaybeSend, for values that get shared between threads by reference
+#[cfg(not(feature = "thread-local"))]
+pub trait MaybeSync: Sync {}
+
+#[cfg(not(feature = "thread-local"))]
+impl<T: ?Sized + Sync> MaybeSync for T {}
+
+#[cfg(feature = "thread-local")]
+pub trait MaybeSync {}
+
+#[cfg(feature = "thread-local")]
+impl<T: ?Sized> MaybeSync for T {}
+
+#[cfg(not(feature = "thread-local"))]
+mod imp {
+    use crate::HookError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    #[cfg(not(feature = "parking_lot"))]
+    use std::sync::RwLock;
+
+    pub(crate) type LockGuard<'a, T> = std::sync::MutexGuard<'a, T>;
+    #[cfg(not(feature = "parking_lot"))]
+    pub(crate) type ReadGuard<'a, T> = std::sync::RwLockReadGuard<'a, T>;
+    #[cfg(not(feature = "parking_lot"))]
+    pub(crate) type WriteGuard<'a, T> = std::sync::RwLockWriteGuard<'a, T>;
+    #[cfg(feature = "parking_lot")]
+    pub(crate) type ReadGuard<'a, T> = parking_lot::RwLockReadGuard<'a, T>;
+    #[cfg(feature = "parking_lot")]
+    pub(crate) type WriteGuard<'a, T> = parking_lot::RwLockWriteGuard<'a, T>;
+
+    #[derive(Default)]
+    pub(crate) struct Lock<T>(Mutex<T>);
+
+    impl<T> Lock<T> {
+        pub(crate) fn new(value: T) -> Self {
+            Lock(Mutex::new(value))
+        }
+
+        /// if some thread panicked while holding the lock, the poison gets cleared instead of
+        /// taking every following render down with it
+        pub(crate) fn lock(&self) -> LockGuard<'_, T> {
+            self.0.lock().unwrap_or_else(|poisoned| {
+                static WARNED: AtomicBool = AtomicBool::new(false);
+                if !WARNED.swap(true, Ordering::Relaxed) {
+                    eprintln!("act: recovering state that got poisoned by a panicking thread");
+                }
+
+                self.0.clear_poison();
+                poisoned.into_inner()
+            })
+        }
+
+        pub(crate) fn try_lock(&self) -> Result<LockGuard<'_, T>, HookError> {
+            self.0.lock().map_err(|_| HookError::PoisonedLock)
+        }
+
+        pub(crate) fn clear_poison(&self) {
+            self.0.clear_poison();
+        }
+    }
+
+    // only ever accessed while holding a Lock, whose poisoning already gets dealt with, except
+    // for the registers held on to by Hook::read_guard
+    #[cfg(not(feature = "parking_lot"))]
+    #[derive(Default)]
+    pub(crate) struct RwCell<T>(RwLock<T>);
+
+    #[cfg(not(feature = "parking_lot"))]
+    impl<T> RwCell<T> {
+        pub(crate) fn new(value: T) -> Self {
+            RwCell(RwLock::new(value))
+        }
+
+        pub(crate) fn read(&self) -> ReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(|poisoned| {
+                self.0.clear_poison();
+                poisoned.into_inner()
+            })
+        }
+
+        pub(crate) fn write(&self) -> WriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(|poisoned| {
+                self.0.clear_poison();
+                poisoned.into_inner()
+            })
+        }
+
+        /// exclusive access without locking, as no one else can hold a reference
+        pub(crate) fn get_mut(&mut self) -> &mut T {
+            self.0
+                .get_mut()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+        }
+    }
+
+    /// like the one of std, but its read guards can be mapped, see Hook::read_guard. There is no
+    /// poisoning to deal with.
+    #[cfg(feature = "parking_lot")]
+    #[derive(Default)]
+    pub(crate) struct RwCell<T>(parking_lot::RwLock<T>);
+
+    #[cfg(feature = "parking_lot")]
+    impl<T> RwCell<T> {
+        pub(crate) fn new(value: T) -> Self {
+            RwCell(parking_lot::RwLock::new(value))
+        }
+
+        pub(crate) fn read(&self) -> ReadGuard<'_, T> {
+            self.0.read()
+        }
+
+        pub(crate) fn write(&self) -> WriteGuard<'_, T> {
+            self.0.write()
+        }
+
+        /// exclusive access without locking, as no one else can hold a reference
+        pub(crate) fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut()
+        }
+    }
+}
+
+#[cfg(feature = "thread-local")]
+mod imp {
+    use crate::HookError;
+    use std::cell::RefCell;
+
+    pub(crate) type LockGuard<'a, T> = std::cell::RefMut<'a, T>;
+    pub(crate) type ReadGuard<'a, T> = std::cell::Ref<'a, T>;
+    pub(crate) type WriteGuard<'a, T> = std::cell::RefMut<'a, T>;
+
+    #[derive(Default)]
+    pub(crate) struct Lock<T>(RefCell<T>);
+
+    impl<T> Lock<T> {
+        pub(crate) fn new(value: T) -> Self {
+            Lock(RefCell::new(value))
+        }
+
+        pub(crate) fn lock(&self) -> LockGuard<'_, T> {
+            self.0.borrow_mut()
+        }
+
+        // nothing can get poisoned without other threads, but this thread may hold the lock
+        pub(crate) fn try_lock(&self) -> Result<LockGuard<'_, T>, HookError> {
+            self.0.try_borrow_mut().map_err(|_| HookError::Locked)
+        }
+
+        pub(crate) fn clear_poison(&self) {}
+    }
+
+    #[derive(Default)]
+    pub(crate) struct RwCell<T>(RefCell<T>);
+
+    impl<T> RwCell<T> {
+        pub(crate) fn new(value: T) -> Self {
+            RwCell(RefCell::new(value))
+        }
+
+        pub(crate) fn read(&self) -> ReadGuard<'_, T> {
+            self.0.borrow()
+        }
+
+        pub(crate) fn write(&self) -> WriteGuard<'_, T> {
+            self.0.borrow_mut()
+        }
+
+        pub(crate) fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut()
+        }
+    }
+}
+
+pub(crate) use imp::{Lock, LockGuard, ReadGuard, RwCell, WriteGuard};
@@ -0,0 +1,102 @@
+#![cfg(feature = "macros")]
+
+use act2::{component, Component, Hook, Runtime};
+
+#[component]
+fn counter(hook: &mut Hook, step: i32) -> i32 {
+    let (count, set) = hook.use_state(0);
+    set(count + step);
+
+    if count > 100 {
+        return -1;
+    }
+    count
+}
+
+#[component]
+fn conditional(hook: &mut Hook, show: bool) {
+    if show {
+        let (_, _) = hook.use_state(0);
+    }
+}
+
+#[test]
+fn render() {
+    let rt = Runtime::new();
+    let mut hook = rt.hook();
+
+    assert_eq!(counter(&mut hook, 2), 0);
+    assert_eq!(counter(&mut hook, 2), 2);
+    assert_eq!(counter(&mut hook, 3), 4);
+}
+
+/// macro input that has to compile, or fail with a helpful error, see tests/ui
+#[test]
+fn ui() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/pass/*.rs");
+    cases.compile_fail("tests/ui/fail/*.rs");
+}
+
+#[test]
+#[should_panic(expected = "used 0 hooks, but 1 on its first render")]
+fn hook_order() {
+    let rt = Runtime::new();
+    let mut hook = rt.hook();
+
+    conditional(&mut hook, true);
+    conditional(&mut hook, false);
+}
+
+#[derive(Component)]
+struct Form {
+    name: String,
+    age: u32,
+    agreed: bool,
+}
+
+#[test]
+fn derive() {
+    let rt = Runtime::new();
+    let init = || Form {
+        name: String::from("anonymous"),
+        age: 0,
+        agreed: false,
+    };
+
+    let mut hook = rt.hook();
+    let (form, setters) = Form::use_fields(&mut hook, init());
+    Form::assert_hook_count(&hook);
+    assert_eq!(Form::HOOK_COUNT, 3);
+    assert_eq!(
+        (form.name.as_str(), form.age, form.agreed),
+        ("anonymous", 0, false)
+    );
+
+    (setters.name)(String::from("ferris"));
+    setters.agreed.call(true);
+
+    let mut hook = rt.hook();
+    let (form, _) = Form::use_fields(&mut hook, init());
+    assert_eq!(
+        (form.name.as_str(), form.age, form.agreed),
+        ("ferris", 0, true)
+    );
+}
+
+#[test]
+#[should_panic(expected = "Form declares 3 hooks, but 4 got used")]
+fn derive_hook_count() {
+    let rt = Runtime::new();
+    let mut hook = rt.hook();
+    let (_, _) = Form::use_fields(
+        &mut hook,
+        Form {
+            name: String::new(),
+            age: 0,
+            agreed: false,
+        },
+    );
+    let (_, _) = hook.use_state(0);
+    Form::assert_hook_count(&hook);
+}
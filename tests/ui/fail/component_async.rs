@@ -0,0 +1,8 @@
+use act2::component;
+
+#[component]
+async fn counter(hook: &mut act2::Hook) -> i32 {
+    hook.use_state(0).0
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use act2::component;
+
+#[component]
+fn counter() -> i32 {
+    0
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use act2::component;
+
+#[component]
+fn counter((hook, step): (&mut act2::Hook, i32)) -> i32 {
+    hook.use_state(step).0
+}
+
+fn main() {}
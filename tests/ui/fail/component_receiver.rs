@@ -0,0 +1,12 @@
+use act2::component;
+
+struct Counter;
+
+impl Counter {
+    #[component]
+    fn render(&self, hook: &mut act2::Hook) -> i32 {
+        hook.use_state(0).0
+    }
+}
+
+fn main() {}
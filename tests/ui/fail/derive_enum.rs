@@ -0,0 +1,9 @@
+use act2::Component;
+
+#[derive(Component)]
+enum Form {
+    Name(String),
+    Age(u32),
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use act2::Component;
+
+#[derive(Component)]
+struct Form<T> {
+    value: T,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use act2::Component;
+
+#[derive(Component)]
+struct Form;
+
+fn main() {}
@@ -0,0 +1,32 @@
+use act2::{component, Hook, Runtime};
+
+#[component]
+fn counter(hook: &mut Hook, step: i32) -> i32 {
+    let (count, set) = hook.use_state(0);
+    set(count + step);
+
+    if count > 100 {
+        return -1;
+    }
+    count
+}
+
+#[component]
+fn labels(hook: &mut Hook) -> impl Iterator<Item = String> {
+    let (count, _) = hook.use_state(3);
+    (0..count).map(|i| i.to_string())
+}
+
+#[component]
+fn nothing(_hook: &mut Hook) {}
+
+fn main() {
+    let rt = Runtime::new();
+    assert_eq!(counter(&mut rt.hook(), 1), 0);
+    assert_eq!(counter(&mut rt.hook(), 1), 1);
+
+    let rt = Runtime::new();
+    assert_eq!(labels(&mut rt.hook()).count(), 3);
+
+    nothing(&mut Runtime::new().hook());
+}
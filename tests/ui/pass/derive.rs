@@ -0,0 +1,28 @@
+use act2::{Component, Runtime};
+
+#[derive(Component)]
+pub struct Form {
+    pub name: String,
+    pub age: u32,
+}
+
+#[derive(Component)]
+struct Empty {}
+
+fn main() {
+    let rt = Runtime::new();
+    let mut hook = rt.hook();
+    let (form, setters) = Form::use_fields(
+        &mut hook,
+        Form {
+            name: String::new(),
+            age: 0,
+        },
+    );
+    Form::assert_hook_count(&hook);
+    (setters.age)(form.age + 1);
+
+    let mut hook = rt.hook();
+    let (Empty {}, EmptySetters {}) = Empty::use_fields(&mut hook, Empty {});
+    assert_eq!(Empty::HOOK_COUNT, 0);
+}